@@ -0,0 +1,719 @@
+use std::collections::{ HashMap, HashSet };
+use std::net::SocketAddr;
+use std::time::{ Duration, Instant };
+use rand::Rng;
+use tokio::sync::mpsc::{ unbounded_channel, UnboundedReceiver, UnboundedSender };
+use crossplatform::id::Id;
+use crossplatform::overlay::{ self, Overlay, PeerLink, Ui, GOSSIP_INTERVAL };
+use crossplatform::proto_rtc::{ RTCData, RTCContent };
+use crossplatform::proto_ws::{ WebSocketData, IceServer, GOING_AWAY_MAX };
+use crossplatform::topology::Neighbourhood;
+use crate::rtc::{ ChannelEvent, Out, Rtc, writer };
+use crate::signaling;
+
+// The native client: the same exchanges with the server as the browser one,
+// and the same overlay from crossplatform
+
+const CONNECT_TIMEOUT: u64 = 30; // s, for a connection signaled through the overlay
+
+pub enum Event {
+	Server(WebSocketData),
+	ServerClosed,
+	Reconnect,
+	Channel(u64, ChannelEvent), // by connection key
+	Input(String), // a line typed by the user
+	Connect(Id), // through the overlay
+	Timeout(u64), // the connection did not open in time
+	Gossip,
+	Error(String)
+}
+
+pub type Events = UnboundedSender<Event>;
+
+// What the user sees
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+	Message(String, String), // from, text
+	Private(String, String),
+	Info(String),
+	Error(String),
+	Neighbours(Vec<(String, Id)>, Vec<Id>), // with the label of their slot, cache
+	Status(String),
+	Id(Id)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+	Message(String),
+	Private(Id, String), // /msg <name> <text>
+	Connect(Id), // /connect <name>
+	Peers, // /peers
+	Reconnect, // /reconnect, without waiting for the backoff
+	Quit // /quit
+}
+
+impl Command {
+	pub fn parse(line: &str) -> Result<Option<Self>, String> {
+		let line = line.trim();
+		if line.is_empty() {
+			return Ok(None);
+		}
+		if !line.starts_with('/') {
+			return Ok(Some(Command::Message(line.to_string())));
+		}
+		let mut words = line.splitn(3, ' ');
+		let command = words.next().unwrap_or_default();
+		let name = |name: Option<&str>| match name.map(str::trim) {
+			Some(name) => name.parse::<Id>().map_err(|e| format!("Invalid name {}: {}", name, e)),
+			None => Err(format!("Usage: {} <name>", command))
+		};
+		Ok(Some(match command {
+			"/connect" => Command::Connect(name(words.next())?),
+			"/msg" => {
+				let to = name(words.next())?;
+				match words.next().map(str::trim) {
+					Some(text) if !text.is_empty() => Command::Private(to, text.to_string()),
+					_ => return Err("Usage: /msg <name> <text>".to_string())
+				}
+			},
+			"/peers" => Command::Peers,
+			"/reconnect" => Command::Reconnect,
+			"/quit" => Command::Quit,
+			_ => return Err(format!("Unknown command: {}", command))
+		}))
+	}
+}
+
+// Full jitter exponential backoff, in ms
+pub fn backoff(attempt: u32) -> u64 {
+	let max = 500u64.saturating_mul(1 << attempt.min(16)).min(30_000);
+	rand::thread_rng().gen_range(0, max + 1)
+}
+
+pub struct Sink(UnboundedSender<Output>);
+
+impl Sink {
+	fn send(&self, output: Output) {
+		let _ = self.0.send(output);
+	}
+}
+
+impl Ui for Sink {
+	fn chat_msg(&self, from: &str, msg: &str) {
+		self.send(Output::Message(from.to_string(), msg.to_string()));
+	}
+
+	fn chat_private(&self, from: &str, msg: &str) {
+		self.send(Output::Private(from.to_string(), msg.to_string()));
+	}
+
+	fn chat_info(&self, msg: &str) {
+		self.send(Output::Info(msg.to_string()));
+	}
+
+	fn neighbours(&self, neighbours: &[(String, Id)], cache: &[Id]) {
+		self.send(Output::Neighbours(neighbours.to_vec(), cache.to_vec()));
+	}
+}
+
+pub enum Link {
+	Rtc(UnboundedSender<Out>),
+	Relay(SocketAddr, UnboundedSender<WebSocketData>) // through the server when WebRTC failed
+}
+
+impl PeerLink for Link {
+	fn send(&self, data: &[u8]) {
+		let _ = match self {
+			Link::Rtc(tx) => tx.send(Out::Binary(data.to_vec())).is_ok(),
+			Link::Relay(addr, server) => server.send(WebSocketData::Relay(data.to_vec(), *addr)).is_ok()
+		};
+	}
+
+	fn relayed(&self) -> Option<SocketAddr> {
+		match self {
+			Link::Relay(addr, _) => Some(*addr),
+			_ => None
+		}
+	}
+
+	fn close(&self) {
+		if let Link::Rtc(tx) = self {
+			let _ = tx.send(Out::Close);
+		}
+	}
+}
+
+pub struct Transport {
+	events: Events,
+	pending: HashSet<Id>
+}
+
+impl overlay::Transport for Transport {
+	type Link = Link;
+
+	fn connect(&self, id: Id) {
+		let _ = self.events.send(Event::Connect(id));
+	}
+
+	fn is_pending(&self, id: Id) -> bool {
+		self.pending.contains(&id)
+	}
+}
+
+pub type Network = Overlay<Transport, Sink>;
+
+// Who does the signaling of a connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+	Server(Option<SocketAddr>), // None until our offer is answered
+	Overlay(Id)
+}
+
+struct Connection {
+	origin: Origin,
+	rtc: Rtc,
+	channel: Option<UnboundedSender<Out>>,
+	peer: Option<Id> // once the channel is open and the peer known
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub url: String, // of the websocket, with the admission token if any
+}
+
+pub struct Client {
+	config: Config,
+	events: Events,
+	output: Sink,
+	server: Option<UnboundedSender<WebSocketData>>,
+	ice_servers: Option<Vec<IceServer>>,
+	turn: Option<(IceServer, Instant, Instant)>, // refresh at, expires
+	turn_requested: bool,
+	session: Option<String>, // resume token
+	neighbourhood: Neighbourhood, // of the network, announced by the server
+	attempts: u32, // failed connections to the server in a row
+	retrying: bool, // a reconnection is scheduled
+	reconnect_in: Option<u64>, // ms, the server is going away
+	network: Option<Network>,
+	connections: HashMap<u64, Connection>,
+	relaying: HashSet<SocketAddr>, // failed handshakes we said hello to, waiting for the hello of the peer
+	next_key: u64
+}
+
+impl Client {
+	pub fn new(config: Config) -> (Self, Events, UnboundedReceiver<Event>, UnboundedReceiver<Output>) {
+		let (events, receiver) = unbounded_channel();
+		let (output, outputs) = unbounded_channel();
+		let client = Client {
+			config,
+			events: events.clone(),
+			output: Sink(output),
+			server: None,
+			ice_servers: None,
+			turn: None,
+			turn_requested: false,
+			session: None,
+			neighbourhood: Neighbourhood::default(),
+			attempts: 0,
+			retrying: false,
+			reconnect_in: None,
+			network: None,
+			connections: HashMap::new(),
+			relaying: HashSet::new(),
+			next_key: 0
+		};
+		(client, events, receiver, outputs)
+	}
+
+	// Until `/quit` or the end of the inputs
+	pub async fn run(mut self, mut receiver: UnboundedReceiver<Event>) {
+		let gossip = self.events.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_millis(GOSSIP_INTERVAL));
+			loop {
+				interval.tick().await;
+				if gossip.send(Event::Gossip).is_err() {
+					break;
+				}
+			}
+		});
+		self.reconnect().await;
+		while let Some(event) = receiver.recv().await {
+			let res = match event {
+				Event::Input(line) => match Command::parse(&line) {
+					Ok(Some(Command::Quit)) => break,
+					Ok(Some(command)) => self.command(command).await,
+					Ok(None) => Ok(()),
+					Err(e) => Err(e)
+				},
+				event => self.execute(event).await
+			};
+			if let Err(e) = res {
+				self.output.send(Output::Error(e));
+			}
+		}
+		for (_, connection) in self.connections.drain() {
+			connection.rtc.close();
+		}
+	}
+
+	async fn execute(&mut self, event: Event) -> Result<(), String> {
+		match event {
+			Event::Server(msg) => self.server_msg(msg).await,
+			Event::ServerClosed => self.server_closed(),
+			Event::Reconnect => {
+				self.retrying = false;
+				self.reconnect().await;
+				Ok(())
+			},
+			Event::Channel(key, event) => self.channel(key, event).await,
+			Event::Connect(id) => self.connect(id).await,
+			Event::Timeout(key) => {
+				if matches!(self.connections.get(&key), Some(connection) if connection.peer.is_none()) {
+					self.drop_connection(key);
+				}
+				Ok(())
+			},
+			Event::Gossip => match self.network.as_mut() {
+				Some(network) => network.gossip(),
+				None => Ok(())
+			},
+			Event::Error(e) => Err(e),
+			Event::Input(_) => Ok(())
+		}
+	}
+
+	fn network(&self) -> Result<&Network, String> {
+		self.network.as_ref().ok_or_else(|| "Not in the network yet".to_string())
+	}
+
+	fn network_mut(&mut self) -> Result<&mut Network, String> {
+		self.network.as_mut().ok_or_else(|| "Not in the network yet".to_string())
+	}
+
+	fn status(&self) {
+		let peers = self.network.as_ref().map_or(0, |network| network.len());
+		self.output.send(Output::Status(match self.server {
+			Some(_) => format!("connected, {} peers", peers),
+			None => format!("offline, {} peers connected", peers)
+		}));
+	}
+
+	fn send_server(&self, msg: WebSocketData) {
+		if let Some(server) = &self.server {
+			let _ = server.send(msg);
+		}
+	}
+
+	async fn reconnect(&mut self) {
+		if self.server.is_some() {
+			return; // a /reconnect came before the backoff
+		}
+		match signaling::connect(&self.config.url, self.events.clone()).await {
+			Ok(server) => {
+				self.server = Some(server);
+				self.attempts = 0;
+				self.output.send(Output::Info("Connected to the server!".to_string()));
+				// a pending offer of the old connection cannot be answered anymore
+				let parked = self.connections.iter()
+					.filter(|(_, connection)| connection.origin == Origin::Server(None))
+					.map(|(key, _)| *key)
+					.collect::<Vec<_>>();
+				parked.into_iter().for_each(|key| self.drop_connection(key));
+				self.send_server(WebSocketData::Resume(self.session.clone()));
+				self.status();
+			},
+			Err(e) => {
+				self.output.send(Output::Error(format!("Cannot connect to the server: {}", e)));
+				let _ = self.server_closed();
+			}
+		}
+	}
+
+	fn server_closed(&mut self) -> Result<(), String> {
+		if self.server.take().is_some() {
+			self.output.send(Output::Info("Server offline, the chat continues with the connected peers".to_string()));
+		}
+		if let Some(network) = self.network.as_mut() {
+			network.remove_links(|link| matches!(link, Link::Relay(..)));
+		}
+		self.status();
+		if self.retrying {
+			return Ok(());
+		}
+		let delay = self.reconnect_in.take().unwrap_or_else(|| backoff(self.attempts));
+		self.attempts += 1;
+		self.retrying = true;
+		let events = self.events.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(delay)).await;
+			let _ = events.send(Event::Reconnect);
+		});
+		Ok(())
+	}
+
+	// Ice servers for a new connection, ask for new TURN credentials when they are getting old
+	fn ice_servers(&mut self) -> Result<Vec<IceServer>, String> {
+		let mut ice = self.ice_servers.clone().ok_or("No ice servers yet")?;
+		if let Some((server, refresh_at, expires)) = &self.turn {
+			let now = Instant::now();
+			if now >= *refresh_at && !self.turn_requested {
+				self.send_server(WebSocketData::TurnCredentials(None));
+				self.turn_requested = true;
+			}
+			if now < *expires {
+				ice.push(server.clone());
+			}
+		}
+		Ok(ice)
+	}
+
+	fn key(&mut self) -> u64 {
+		self.next_key += 1;
+		self.next_key
+	}
+
+	fn find(&self, origin: Origin) -> Option<u64> {
+		self.connections.iter().find(|(_, connection)| connection.origin == origin).map(|(key, _)| *key)
+	}
+
+	fn drop_connection(&mut self, key: u64) {
+		if let Some(connection) = self.connections.remove(&key) {
+			if let (Origin::Overlay(id), Some(network)) = (connection.origin, self.network.as_mut()) {
+				network.transport_mut().pending.remove(&id);
+			}
+			connection.rtc.close();
+		}
+	}
+
+	// Ask the server for a peer
+	async fn offer_server(&mut self) -> Result<(), String> {
+		if self.find(Origin::Server(None)).is_some() {
+			return Ok(());
+		}
+		let ice = self.ice_servers()?;
+		let key = self.key();
+		let (rtc, sdp) = Rtc::offer(key, &ice, &self.events).await?;
+		self.connections.insert(key, Connection { origin: Origin::Server(None), rtc, channel: None, peer: None });
+		self.send_server(WebSocketData::OfferSDP(sdp, None));
+		self.output.send(Output::Info("Asking the server for a peer...".to_string()));
+		Ok(())
+	}
+
+	async fn server_msg(&mut self, msg: WebSocketData) -> Result<(), String> {
+		match msg {
+			WebSocketData::IceServers(ice) => {
+				self.ice_servers = Some(ice);
+				self.offer_server().await
+			},
+			WebSocketData::TurnCredentials(Some(creds)) => {
+				let now = Instant::now();
+				// ask for new ones at 80% of the ttl
+				let (refresh_at, expires) = (now + Duration::from_millis(creds.ttl * 800), now + Duration::from_secs(creds.ttl));
+				self.turn = Some((creds.server, refresh_at, expires));
+				self.turn_requested = false;
+				Ok(())
+			},
+			WebSocketData::OfferSDP(sdp, Some(addr)) => {
+				let ice = self.ice_servers()?;
+				let key = self.key();
+				let (rtc, answer) = Rtc::answer(key, &ice, &self.events, &sdp).await?;
+				self.connections.insert(key, Connection { origin: Origin::Server(Some(addr)), rtc, channel: None, peer: None });
+				self.send_server(WebSocketData::AnswerSDP(answer, addr));
+				Ok(())
+			},
+			WebSocketData::AnswerSDP(sdp, addr) => {
+				let key = self.find(Origin::Server(None)).ok_or("Answer without offer")?;
+				let connection = self.connections.get_mut(&key).ok_or("Unknown connection")?;
+				connection.origin = Origin::Server(Some(addr));
+				connection.rtc.accept(&sdp).await
+			},
+			WebSocketData::IceCandidate(candidate, addr) => {
+				let key = self.find(Origin::Server(Some(addr))).ok_or("Ice candidate for an unknown connection")?;
+				self.connections[&key].rtc.candidate(&candidate).await
+			},
+			WebSocketData::Session(id, token) => {
+				self.session = Some(token);
+				match &self.network {
+					Some(network) if network.id == id => (),
+					previous => {
+						if previous.is_some() {
+							// the peers were chosen for the old id
+							self.output.send(Output::Info(format!("The session expired, your new id is: {}", id.to_name())));
+						}
+						let transport = Transport { events: self.events.clone(), pending: HashSet::new() };
+						self.network = Some(Overlay::new(id, self.neighbourhood, transport, Sink(self.output.0.clone())));
+						self.output.send(Output::Id(id));
+					}
+				}
+				Ok(())
+			},
+			WebSocketData::Relay(data, addr) => self.relay(data, addr).await,
+			WebSocketData::RelayClosed(addr) => {
+				self.relaying.remove(&addr);
+				self.output.send(Output::Info("The server cannot relay this connection".to_string()));
+				let network = self.network_mut()?;
+				match network.relayed(addr) {
+					Some(id) => network.remove(id),
+					None => Ok(())
+				}
+			},
+			WebSocketData::GoingAway(window) => {
+				// spread the reconnections of every client over the window
+				self.reconnect_in = Some(rand::thread_rng().gen_range(0, window.saturating_mul(1000).min(GOING_AWAY_MAX) + 1));
+				self.output.send(Output::Info("The server is shutting down".to_string()));
+				Ok(())
+			},
+			WebSocketData::Notice(notice) => {
+				self.output.send(Output::Private("Server".to_string(), notice));
+				Ok(())
+			},
+			WebSocketData::Neighbourhood(neighbourhood) => {
+				self.neighbourhood = neighbourhood;
+				match &mut self.network {
+					Some(network) if *network.topology() != neighbourhood => network.set_topology(neighbourhood),
+					_ => ()
+				}
+				Ok(())
+			},
+			msg => Err(format!("Cannot handle from: {:?}", msg))
+		}
+	}
+
+	// Tell the peer we fall back on the server relay
+	fn relay_hello(&mut self, addr: SocketAddr) -> Result<(), String> {
+		let hello = RTCData { to: None, id: 0, timestamp: 0, from: self.network()?.id, content: RTCContent::Hello };
+		self.send_server(WebSocketData::Relay(hello.into_u8()?, addr));
+		self.relaying.insert(addr);
+		Ok(())
+	}
+
+	async fn relay(&mut self, data: Vec<u8>, addr: SocketAddr) -> Result<(), String> {
+		let data = RTCData::from_u8(data)?;
+		if let Some(id) = self.network()?.relayed(addr) {
+			return self.rtc_message(id, data).await;
+		}
+		match data.content {
+			RTCContent::Hello => {
+				// the peer gave up first
+				if let Some(key) = self.find(Origin::Server(Some(addr))) {
+					self.drop_connection(key);
+					self.relay_hello(addr)?;
+				}
+				// only from the peer of a handshake that failed
+				if !self.relaying.remove(&addr) {
+					return Err(format!("Unsolicited hello relayed from {}", addr));
+				}
+				let server = self.server.clone().ok_or("No server")?;
+				self.network_mut()?.insert(Link::Relay(addr, server), data.from);
+				self.output.send(Output::Info(format!("Connection relayed by the server with {}", data.from.to_name())));
+				self.status();
+				Ok(())
+			},
+			_ => Err(format!("Relayed data from an unknown peer: {}", addr))
+		}
+	}
+
+	async fn channel(&mut self, key: u64, event: ChannelEvent) -> Result<(), String> {
+		match event {
+			ChannelEvent::Open(channel) => {
+				let me = self.network()?.id;
+				let connection = self.connections.get_mut(&key).ok_or("Channel of an unknown connection")?;
+				let channel = writer(channel);
+				match connection.origin {
+					// we learn who is on the other side
+					Origin::Server(_) => {
+						let _ = channel.send(Out::Text(me.to_name()));
+						connection.channel = Some(channel);
+					},
+					Origin::Overlay(id) => {
+						connection.peer = Some(id);
+						self.link(id, channel)?;
+					}
+				}
+				Ok(())
+			},
+			ChannelEvent::Text(name) => {
+				let connection = self.connections.get(&key).ok_or("Text from an unknown connection")?;
+				if connection.peer.is_some() {
+					return Err(format!("Unexpected text from a peer: {}", name));
+				}
+				let id = match name.parse::<Id>() {
+					Ok(id) => id,
+					Err(e) => {
+						self.drop_connection(key);
+						return Err(format!("Invalid id from the peer: {}", e));
+					}
+				};
+				let connection = self.connections.get_mut(&key).ok_or("Text from an unknown connection")?;
+				let channel = connection.channel.clone().ok_or("Text before the channel opened")?;
+				connection.peer = Some(id);
+				self.link(id, channel)
+			},
+			ChannelEvent::Binary(data) => {
+				let id = self.connections.get(&key).and_then(|connection| connection.peer).ok_or("Data from an unknown peer")?;
+				self.rtc_message(id, RTCData::from_u8(data)?).await
+			},
+			ChannelEvent::Closed => {
+				let peer = self.connections.get(&key).and_then(|connection| connection.peer);
+				self.drop_connection(key);
+				// unless the overlay closed it
+				if let Some(id) = peer.filter(|id| self.network.as_ref().is_some_and(|network| network.contains(*id))) {
+					self.network_mut()?.remove(id)?;
+					self.status();
+				}
+				Ok(())
+			},
+			ChannelEvent::Failed => {
+				let origin = self.connections.get(&key).map(|connection| connection.origin);
+				self.drop_connection(key);
+				match origin {
+					Some(Origin::Server(Some(addr))) => {
+						self.output.send(Output::Info("Cannot reach the peer directly, asking the server to relay...".to_string()));
+						self.relay_hello(addr)
+					},
+					Some(Origin::Overlay(id)) => {
+						self.output.send(Output::Info(format!("Cannot reach {} directly", id.to_name())));
+						Ok(())
+					},
+					_ => Ok(())
+				}
+			}
+		}
+	}
+
+	fn link(&mut self, id: Id, channel: UnboundedSender<Out>) -> Result<(), String> {
+		let network = self.network_mut()?;
+		network.transport_mut().pending.remove(&id);
+		network.insert(Link::Rtc(channel), id);
+		self.output.send(Output::Info(format!("Connection openned with {}", id.to_name())));
+		self.status();
+		Ok(())
+	}
+
+	async fn rtc_message(&mut self, from: Id, data: RTCData) -> Result<(), String> {
+		let network = self.network()?;
+		match data.content {
+			RTCContent::Offer(_) | RTCContent::Answer(_) | RTCContent::IceCandidate(_) if data.to == Some(network.id)
+			=> self.overlay_signal(data).await,
+			_ => network.process(&data, from)
+		}
+	}
+
+	// Give up the connections that never open
+	fn timeout(&self, key: u64) {
+		let events = self.events.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_secs(CONNECT_TIMEOUT)).await;
+			let _ = events.send(Event::Timeout(key));
+		});
+	}
+
+	// Open a connection with `id` without the server, the signaling is routed by our peers
+	async fn connect(&mut self, id: Id) -> Result<(), String> {
+		if self.network()?.contains(id) {
+			return Err(format!("Already connected to {}", id.to_name()));
+		}
+		let ice = self.ice_servers()?;
+		let key = self.key();
+		let (rtc, sdp) = Rtc::offer(key, &ice, &self.events).await?;
+		self.connections.insert(key, Connection { origin: Origin::Overlay(id), rtc, channel: None, peer: None });
+		let network = self.network_mut()?;
+		network.transport_mut().pending.insert(id);
+		self.timeout(key);
+		if let Err(e) = self.network()?.send_to(RTCContent::Offer(sdp), id) {
+			self.drop_connection(key);
+			return Err(e);
+		}
+		self.output.send(Output::Info(format!("Asking {} for a connection through the peers...", id.to_name())));
+		Ok(())
+	}
+
+	async fn overlay_signal(&mut self, data: RTCData) -> Result<(), String> {
+		let peer = data.from;
+		match data.content {
+			RTCContent::Offer(sdp) => {
+				if self.network()?.contains(peer) {
+					return Err(format!("Offer from {} but already connected", peer.to_name()));
+				}
+				let ice = self.ice_servers()?;
+				let key = self.key();
+				let (rtc, answer) = Rtc::answer(key, &ice, &self.events, &sdp).await?;
+				self.connections.insert(key, Connection { origin: Origin::Overlay(peer), rtc, channel: None, peer: None });
+				self.network_mut()?.transport_mut().pending.insert(peer);
+				self.timeout(key);
+				self.network()?.send_to(RTCContent::Answer(answer), peer)
+			},
+			RTCContent::Answer(sdp) => {
+				let key = self.find(Origin::Overlay(peer)).ok_or("Answer for an unknown handshake")?;
+				self.connections[&key].rtc.accept(&sdp).await
+			},
+			RTCContent::IceCandidate(candidate) => {
+				let key = self.find(Origin::Overlay(peer)).ok_or("Ice candidate for an unknown handshake")?;
+				self.connections[&key].rtc.candidate(&candidate).await
+			},
+			_ => Err("Not a signaling message".to_string())
+		}
+	}
+
+	async fn command(&mut self, command: Command) -> Result<(), String> {
+		match command {
+			Command::Message(text) => {
+				self.network()?.broadcast(RTCContent::Message(text.clone()))?;
+				self.output.send(Output::Message("Me".to_string(), text));
+				Ok(())
+			},
+			Command::Private(to, text) => {
+				self.network()?.send_to(RTCContent::Message(text.clone()), to)?;
+				self.output.send(Output::Private(format!("Me -> {}", to.to_name()), text));
+				Ok(())
+			},
+			Command::Connect(id) => self.connect(id).await,
+			Command::Peers => {
+				self.network()?.refresh();
+				self.status();
+				Ok(())
+			},
+			Command::Reconnect => match self.server {
+				Some(_) => Err("Already connected to the server".to_string()),
+				None => {
+					self.attempts = 0;
+					self.reconnect().await;
+					Ok(())
+				}
+			},
+			Command::Quit => Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crossplatform::id::Id;
+	use super::{ Command, backoff };
+
+	#[test]
+	fn commands() {
+		let id = Id::new(12, -3);
+		let name = id.to_name();
+		assert_eq!(Command::parse("  "), Ok(None));
+		assert_eq!(Command::parse(" hello "), Ok(Some(Command::Message("hello".to_string()))));
+		assert_eq!(Command::parse(&format!("/connect {}", name)), Ok(Some(Command::Connect(id))));
+		assert_eq!(Command::parse(&format!("/msg {} hi there", name)), Ok(Some(Command::Private(id, "hi there".to_string()))));
+		assert_eq!(Command::parse("/peers"), Ok(Some(Command::Peers)));
+		assert_eq!(Command::parse("/reconnect"), Ok(Some(Command::Reconnect)));
+		assert_eq!(Command::parse("/quit"), Ok(Some(Command::Quit)));
+		assert!(Command::parse("/connect").is_err());
+		assert!(Command::parse("/connect not*valid").is_err());
+		assert!(Command::parse(&format!("/msg {}", name)).is_err());
+		assert!(Command::parse("/nope").is_err());
+	}
+
+	#[test]
+	fn backoffs() {
+		for attempt in 0..40 {
+			assert!(backoff(attempt) <= 30_000);
+		}
+		assert!(backoff(0) <= 500);
+	}
+}
@@ -0,0 +1,5 @@
+// Native p2p chat client, without a browser
+
+pub mod client;
+pub mod rtc;
+pub mod signaling;
@@ -0,0 +1,53 @@
+use structopt::StructOpt;
+use tokio::io::{ AsyncBufReadExt, BufReader };
+use client::client::{ Client, Config, Event, Output };
+use client::signaling;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "client", about = "Chat on the p2p network from a terminal")]
+struct Opt {
+	/// Websocket of the server
+	#[structopt(short, long, default_value = "ws://localhost:8088")]
+	server: String,
+
+	/// Admission token, when the server requires one
+	#[structopt(short, long)]
+	token: Option<String>
+}
+
+fn print(output: Output) {
+	match output {
+		Output::Message(from, text) => println!("{}: {}", from, text),
+		Output::Private(from, text) => println!("[private] {}: {}", from, text),
+		Output::Info(info) => println!("* {}", info),
+		Output::Error(e) => eprintln!("! {}", e),
+		Output::Neighbours(neighbours, cache) => {
+			let neighbours = neighbours.iter().map(|(label, id)| format!("{}: {}", label.to_lowercase(), id.to_name())).collect::<Vec<_>>();
+			println!("* {}, {} in cache", if neighbours.is_empty() { "no neighbour".to_string() } else { neighbours.join(", ") }, cache.len());
+		},
+		Output::Status(status) => println!("* {}", status),
+		Output::Id(id) => println!("* Your id: {}", id.to_name())
+	}
+}
+
+#[tokio::main]
+async fn main() {
+	let opt = Opt::from_args();
+	let config = Config { url: signaling::url(&opt.server, opt.token.as_deref()) };
+	let (client, events, receiver, mut outputs) = Client::new(config);
+	tokio::spawn(async move {
+		while let Some(output) = outputs.recv().await {
+			print(output);
+		}
+	});
+	tokio::spawn(async move {
+		let mut lines = BufReader::new(tokio::io::stdin()).lines();
+		while let Ok(Some(line)) = lines.next_line().await {
+			if events.send(Event::Input(line)).is_err() {
+				break;
+			}
+		}
+		let _ = events.send(Event::Input("/quit".to_string()));
+	});
+	client.run(receiver).await;
+}
@@ -0,0 +1,170 @@
+use std::sync::{ Arc, Weak };
+use bytes::Bytes;
+use tokio::sync::mpsc::{ unbounded_channel, UnboundedSender };
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use crossplatform::proto_ws::{ IceServer, IceCandidateStruct };
+use crate::client::{ Event, Events };
+
+// WebRTC data channels with the pure Rust stack, the candidates are gathered
+// before the offer or answer is sent so ours never need to be trickled
+
+pub enum ChannelEvent {
+	Open(Arc<RTCDataChannel>),
+	Text(String), // the peer id, first frame of a connection brokered by the server
+	Binary(Vec<u8>), // RTCData
+	Closed,
+	Failed // ice cannot connect us
+}
+
+// What is written on a data channel
+#[derive(Debug)]
+pub enum Out {
+	Text(String),
+	Binary(Vec<u8>),
+	Close // the overlay dropped the link
+}
+
+fn ice_servers(servers: &[IceServer]) -> Vec<RTCIceServer> {
+	servers.iter().map(|server| RTCIceServer {
+		urls: server.urls.clone(),
+		username: server.username.clone().unwrap_or_default(),
+		credential: server.credential.clone().unwrap_or_default(),
+		..Default::default()
+	}).collect()
+}
+
+fn send(events: &Events, key: u64, event: ChannelEvent) {
+	// the client is gone when it fails
+	let _ = events.send(Event::Channel(key, event));
+}
+
+// Forward the events of `channel`, tagged with `key`
+fn watch(key: u64, channel: &Arc<RTCDataChannel>, events: &Events) {
+	let (open, message, close) = (events.clone(), events.clone(), events.clone());
+	let weak: Weak<RTCDataChannel> = Arc::downgrade(channel);
+	channel.on_open(Box::new(move || {
+		if let Some(channel) = weak.upgrade() {
+			send(&open, key, ChannelEvent::Open(channel));
+		}
+		Box::pin(async {})
+	}));
+	channel.on_message(Box::new(move |msg: DataChannelMessage| {
+		let event = if msg.is_string {
+			ChannelEvent::Text(String::from_utf8_lossy(&msg.data).into_owned())
+		} else {
+			ChannelEvent::Binary(msg.data.to_vec())
+		};
+		send(&message, key, event);
+		Box::pin(async {})
+	}));
+	channel.on_close(Box::new(move || {
+		send(&close, key, ChannelEvent::Closed);
+		Box::pin(async {})
+	}));
+}
+
+// Write the frames one after the other, in order
+pub fn writer(channel: Arc<RTCDataChannel>) -> UnboundedSender<Out> {
+	let (tx, mut rx) = unbounded_channel::<Out>();
+	tokio::spawn(async move {
+		while let Some(out) = rx.recv().await {
+			let res = match out {
+				Out::Text(text) => channel.send_text(text).await,
+				Out::Binary(data) => channel.send(&Bytes::from(data)).await,
+				Out::Close => {
+					let _ = channel.close().await;
+					break;
+				}
+			};
+			if res.is_err() {
+				break;
+			}
+		}
+	});
+	tx
+}
+
+pub struct Rtc {
+	pc: Arc<RTCPeerConnection>
+}
+
+impl Rtc {
+	async fn new(key: u64, ice: &[IceServer], events: &Events) -> Result<Self, String> {
+		let api = APIBuilder::new().build();
+		let config = RTCConfiguration { ice_servers: ice_servers(ice), ..Default::default() };
+		let pc = Arc::new(api.new_peer_connection(config).await.map_err(|e| e.to_string())?);
+		let failed = events.clone();
+		pc.on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
+			if state == RTCIceConnectionState::Failed {
+				send(&failed, key, ChannelEvent::Failed);
+			}
+			Box::pin(async {})
+		}));
+		// the channel of the offer
+		let incoming = events.clone();
+		pc.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+			watch(key, &channel, &incoming);
+			Box::pin(async {})
+		}));
+		Ok(Rtc { pc })
+	}
+
+	// Our local description once every candidate is in
+	async fn local(&self, description: RTCSessionDescription) -> Result<String, String> {
+		let mut gathered = self.pc.gathering_complete_promise().await;
+		self.pc.set_local_description(description).await.map_err(|e| e.to_string())?;
+		let _ = gathered.recv().await;
+		self.pc.local_description().await.map(|description| description.sdp).ok_or_else(|| "No local description".to_string())
+	}
+
+	// A new connection and its offer
+	pub async fn offer(key: u64, ice: &[IceServer], events: &Events) -> Result<(Self, String), String> {
+		let rtc = Rtc::new(key, ice, events).await?;
+		let channel = rtc.pc.create_data_channel("my-data-channel", None).await.map_err(|e| e.to_string())?;
+		watch(key, &channel, events);
+		let offer = rtc.pc.create_offer(None).await.map_err(|e| e.to_string())?;
+		let sdp = rtc.local(offer).await?;
+		Ok((rtc, sdp))
+	}
+
+	// A new connection answering `offer`
+	pub async fn answer(key: u64, ice: &[IceServer], events: &Events, offer: &str) -> Result<(Self, String), String> {
+		let rtc = Rtc::new(key, ice, events).await?;
+		let offer = RTCSessionDescription::offer(offer.to_string()).map_err(|e| e.to_string())?;
+		rtc.pc.set_remote_description(offer).await.map_err(|e| e.to_string())?;
+		let answer = rtc.pc.create_answer(None).await.map_err(|e| e.to_string())?;
+		let sdp = rtc.local(answer).await?;
+		Ok((rtc, sdp))
+	}
+
+	// The answer to our offer
+	pub async fn accept(&self, answer: &str) -> Result<(), String> {
+		let answer = RTCSessionDescription::answer(answer.to_string()).map_err(|e| e.to_string())?;
+		self.pc.set_remote_description(answer).await.map_err(|e| e.to_string())
+	}
+
+	// Trickled by a browser
+	pub async fn candidate(&self, candidate: &IceCandidateStruct) -> Result<(), String> {
+		let candidate = RTCIceCandidateInit {
+			candidate: candidate.candidate.clone(),
+			sdp_mid: candidate.sdp_mid.clone(),
+			sdp_mline_index: candidate.sdp_m_line_index,
+			username_fragment: None
+		};
+		self.pc.add_ice_candidate(candidate).await.map_err(|e| e.to_string())
+	}
+
+	pub fn close(self) {
+		tokio::spawn(async move {
+			let _ = self.pc.close().await;
+		});
+	}
+}
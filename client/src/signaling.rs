@@ -0,0 +1,68 @@
+use futures::{ SinkExt, StreamExt };
+use tokio::sync::mpsc::{ unbounded_channel, UnboundedSender };
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use crossplatform::proto_ws::{ WebSocketData, subprotocol };
+use crate::client::{ Event, Events };
+
+// The websocket with the server, the frames are bincode encoded WebSocketData
+
+// Url of the websocket, with the admission token if any
+pub fn url(server: &str, token: Option<&str>) -> String {
+	match token {
+		Some(token) if server.contains('?') => format!("{}&token={}", server, token),
+		Some(token) => format!("{}?token={}", server, token),
+		None => server.to_string()
+	}
+}
+
+// Connect, the received frames and the end of the connection become events
+pub async fn connect(url: &str, events: Events) -> Result<UnboundedSender<WebSocketData>, String> {
+	let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+	let protocol = HeaderValue::from_str(&subprotocol()).map_err(|e| e.to_string())?;
+	request.headers_mut().insert("Sec-WebSocket-Protocol", protocol);
+	let (stream, _) = connect_async(request).await.map_err(|e| e.to_string())?;
+	let (mut sink, mut stream) = stream.split();
+
+	let (tx, mut rx) = unbounded_channel::<WebSocketData>();
+	tokio::spawn(async move {
+		while let Some(msg) = rx.recv().await {
+			let data = match msg.into_u8() {
+				Ok(data) => data,
+				Err(_) => continue
+			};
+			if sink.send(Message::Binary(data)).await.is_err() {
+				break;
+			}
+		}
+		let _ = sink.close().await;
+	});
+	tokio::spawn(async move {
+		while let Some(Ok(msg)) = stream.next().await {
+			match msg {
+				Message::Binary(data) => match WebSocketData::from_u8(data) {
+					Ok(msg) => if events.send(Event::Server(msg)).is_err() { return },
+					Err(e) => { let _ = events.send(Event::Error(format!("Invalid frame from the server: {}", e))); }
+				},
+				Message::Close(_) => break,
+				_ => ()
+			}
+		}
+		let _ = events.send(Event::ServerClosed);
+	});
+	Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::url;
+
+	#[test]
+	fn token() {
+		assert_eq!(url("ws://localhost:8088", None), "ws://localhost:8088");
+		assert_eq!(url("ws://localhost:8088/", Some("1.ab")), "ws://localhost:8088/?token=1.ab");
+		assert_eq!(url("ws://localhost:8088/?a=b", Some("1.ab")), "ws://localhost:8088/?a=b&token=1.ab");
+	}
+}
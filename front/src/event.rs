@@ -1,21 +1,23 @@
+use std::net::SocketAddr;
 use wasm_bindgen::prelude::*;
 use web_sys::{ RtcDataChannel };
-use crossplatform::proto_ws::WebSocketData;
+use crossplatform::proto_ws::{ WebSocketData, GOING_AWAY_MAX };
 use crossplatform::proto_rtc::{ RTCData, RTCContent };
 use crossplatform::id::Id;
 
 // use crate::{ log, console_log };
 use crate::Sender;
 use crate::html::{ ids, Html };
-use crate::webrtc::RTCSocket;
+use crate::webrtc::{ RTCSocket, Signaling };
 use crate::websocket::WebSocket;
-use crate::streams::{ Sockets, Socket, State, Pstream, Data };
-use crate::p2p::Network;
+use crate::streams::{ Sockets, Socket, State, Pstream, Data, Turn, backoff };
+use crate::p2p::{ Network, GOSSIP_INTERVAL };
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum Event {
 	ServerDisconnect,
+	ServerReconnect,
 	ServerConnected,
 	ServerMessage(WebSocketData), // TODO: Message struct
 	Html(String, JsValue), // event from html
@@ -23,8 +25,16 @@ pub enum Event {
 	TmpId(String), // Should be Id
 	RtcState(bool), // Become RTCDisconnect with Option<Id> tmp if none
 	RTCMessage(Id, RTCData),
-	RTCDisconnect(Id)
-	// RTCMessage
+	RTCDisconnect(Id),
+	IceFailed, // tmp cannot connect, fallback on the server relay
+	// Signaling through the overlay
+	Signal(Id, RTCContent), // to route up to the peer
+	OverlayConnect(Id),
+	OverlayChannel(Id, RtcDataChannel),
+	OverlayOpen(Id),
+	OverlayFailed(Id),
+	OverlayTimeout(Id, u64), // the handshake started at this time
+	Gossip // share our neighbours, periodic
 }
 
 impl Event {
@@ -32,6 +42,7 @@ impl Event {
 		match self {
 			// Server Event
 			Event::ServerDisconnect => Event::server_disconnect(socks, html, sender),
+			Event::ServerReconnect => Event::server_reconnect(socks, html, sender),
 			Event::ServerConnected => Event::server_connected(socks, sender, html).await,
 			Event::ServerMessage(msg) => Event::server_msg(socks, sender, msg, html).await,
 
@@ -39,33 +50,148 @@ impl Event {
 			Event::DCObj(dc) => Event::dcobj(socks, dc, sender),
 			Event::TmpId(msg) => Event::tmp_id(socks, msg, html, sender),
 			Event::RtcState(state) => Event::rtc_state(socks, state, html),
-			Event::RTCMessage(id, data) => socks.network.as_ref().ok_or("Should have a network")?.process(&data, id),
-			Event::RTCDisconnect(id) => socks.network.as_mut().ok_or("Should have a network")?.remove(id),
+			Event::RTCMessage(id, data) => Event::rtc_message(socks, id, data, sender, html).await,
+			Event::RTCDisconnect(id) => Event::rtc_disconnect(socks, id, html),
+			Event::IceFailed => Event::ice_failed(socks, sender, html).await,
+			Event::Signal(id, content) => socks.network.as_ref().ok_or("Should have a network")?.send_to(content, id),
+			Event::OverlayConnect(id) => Event::overlay_connect(socks, id, sender, html).await,
+			Event::OverlayChannel(id, dc) => {
+				let network = socks.network.as_mut().ok_or("Should have a network")?;
+				let socket = network.pending(id).ok_or("Data channel for an unknown handshake")?;
+				socket.set_overlay_dc(dc, id, sender);
+				Ok(())
+			},
+			Event::OverlayOpen(id) => Event::overlay_open(socks, id, sender, html),
+			Event::OverlayFailed(id) => {
+				if let Some(socket) = socks.network.as_mut().ok_or("Should have a network")?.take_pending(id) {
+					socket.delete();
+					html.chat_info(&format!("Cannot reach {} directly", id.to_name()));
+				}
+				Ok(())
+			},
+			Event::OverlayTimeout(id, started) => {
+				if let Some(socket) = socks.network.as_mut().ok_or("Should have a network")?.take_expired(id, started) {
+					socket.delete();
+					html.chat_info(&format!("No connection with {} through the peers", id.to_name()));
+				}
+				Ok(())
+			},
+			Event::Gossip => {
+				sender.send_later(Event::Gossip, GOSSIP_INTERVAL);
+				match socks.network.as_mut() {
+					Some(network) => network.gossip(),
+					None => Ok(())
+				}
+			},
 			// Html Event
-			Event::Html(id, msg) => Event::html(socks, id, msg, html),
+			Event::Html(id, msg) => Event::html(socks, id, msg, html, sender),
 			// data => Err(format!("cannot handle {:?}", data))
 		}
 	}
 
 	fn tmp_id(socks: &mut Sockets, msg: String, html: &Html, sender: Sender) -> Result<(), String> {
 		let network = socks.network.as_mut().ok_or("You are not connected to the network")?;
-		let socket = std::mem::replace(&mut socks.tmp.socket, None);
+		let socket = socks.tmp.socket.take();
 		html.fill(ids::TMP_PEER_ID, "None");
 		socks.tmp.state = State::Disconnected(None);
 		// socks.tmp.
 		if let Some(Socket::WebRTC(socket)) = socket {
-			let peer_id = Id::from_name(msg.as_str());
+			// the first frame of the peer, nothing checked it yet
+			let peer_id = match msg.parse::<Id>() {
+				Ok(peer_id) => peer_id,
+				Err(e) => {
+					socket.delete();
+					return Err(format!("Invalid id from the peer: {}", e));
+				}
+			};
 			network.insert(socket.clone(), peer_id, sender);
 			// TODO: delete tmp (state... , not cbs)
 
 			html.chat_info(format!("Connection openned with {}", msg).as_str());
 			html.chat_msg("Peer", msg.as_str());
+			html.fill(ids::SERVER_STATUS_ID, &socks.status());
 			Ok(())
 		} else {
 			Err("Invlaid type for tmp socket".to_string())
 		}
 	}
 
+	async fn rtc_message(socks: &mut Sockets<'_>, id: Id, data: RTCData, sender: Sender, html: &Html) -> Result<(), String> {
+		let network = socks.network.as_ref().ok_or("Should have a network")?;
+		match data.content {
+			RTCContent::Offer(_) | RTCContent::Answer(_) | RTCContent::IceCandidate(_) if data.to == Some(network.id)
+			=> Event::overlay_signal(socks, data, sender, html).await,
+			_ => network.process(&data, id)
+		}
+	}
+
+	// Open a connection with `id` without the server, the signaling is routed by our peers
+	async fn overlay_connect(socks: &mut Sockets<'_>, id: Id, sender: Sender, html: &Html) -> Result<(), String> {
+		if socks.network.as_ref().ok_or("Should have a network")?.contains(id) {
+			return Err(format!("Already connected to {}", id.to_name()));
+		}
+		let ice = socks.ice_servers()?;
+		let socket = RTCSocket::new(&socks.server, &ice, sender, html, false).await.map_err(|e| format!("{:?}", e))?;
+		let sdp = socket.local_sdp().ok_or("No local offer")?;
+		let network = socks.network.as_mut().ok_or("Should have a network")?;
+		// without a route to `id` nothing would ever answer
+		if let Err(e) = network.send_to(RTCContent::Offer(sdp), id) {
+			socket.delete();
+			return Err(e);
+		}
+		network.add_pending(id, socket);
+		html.chat_info(&format!("Asking {} for a connection through the peers...", id.to_name()));
+		Ok(())
+	}
+
+	async fn overlay_signal(socks: &mut Sockets<'_>, data: RTCData, sender: Sender, html: &Html) -> Result<(), String> {
+		let peer = data.from;
+		let signaling = Signaling::Overlay(sender.clone(), peer);
+		match data.content {
+			RTCContent::Offer(sdp) => {
+				let network = socks.network.as_ref().ok_or("Should have a network")?;
+				if network.contains(peer) {
+					return Err(format!("Offer from {} but already connected", peer.to_name()));
+				}
+				let ice = socks.ice_servers()?;
+				let mut socket = RTCSocket::new(&socks.server, &ice, sender.clone(), html, false).await.map_err(|e| format!("{:?}", e))?;
+				if let Err(e) = socket.offer(signaling, &sdp, sender).await {
+					socket.delete();
+					return Err(format!("{:?}", e));
+				}
+				socks.network.as_mut().ok_or("Should have a network")?.add_pending(peer, socket);
+				Ok(())
+			},
+			RTCContent::Answer(sdp) => {
+				let network = socks.network.as_mut().ok_or("Should have a network")?;
+				let id = network.id;
+				let socket = network.pending(peer).ok_or("Answer for an unknown handshake")?;
+				socket.answer(signaling, &sdp, sender, id).await.map_err(|e| format!("{:?}", e))
+			},
+			RTCContent::IceCandidate(candidate) => {
+				let network = socks.network.as_mut().ok_or("Should have a network")?;
+				let socket = network.pending(peer).ok_or("Ice candidate for an unknown handshake")?;
+				socket.ice_candidate(&candidate).await.map_err(|e| format!("{:?}", e))
+			},
+			_ => Err("Not a signaling message".to_string())
+		}
+	}
+
+	fn overlay_open(socks: &mut Sockets, id: Id, sender: Sender, html: &Html) -> Result<(), String> {
+		let network = socks.network.as_mut().ok_or("Should have a network")?;
+		let socket = network.take_pending(id).ok_or("Unknown overlay connection")?;
+		network.insert(socket, id, sender);
+		html.chat_info(&format!("Connection openned with {} through the peers", id.to_name()));
+		html.fill(ids::SERVER_STATUS_ID, &socks.status());
+		Ok(())
+	}
+
+	fn rtc_disconnect(socks: &mut Sockets, id: Id, html: &Html) -> Result<(), String> {
+		socks.network.as_mut().ok_or("Should have a network")?.remove(id)?;
+		html.fill(ids::SERVER_STATUS_ID, &socks.status());
+		Ok(())
+	}
+
 	// True version is a duplicat fo tmp_i
 	fn rtc_state(socks: &mut Sockets, state: bool, html: &Html) -> Result<(), String> {
 		match state {
@@ -85,6 +211,70 @@ impl Event {
 		Ok(())
 	}
 
+	async fn ice_failed(socks: &mut Sockets<'_>, sender: Sender, html: &Html) -> Result<(), String> {
+		match socks.tmp.state {
+			State::Locked(addr) => Event::relay_hello(socks, addr, sender, html).await,
+			_ => Err("Ice failed but tmp isnt locked with an addr".to_string())
+		}
+	}
+
+	// Drop the tmp RtcPeerConnection, say hello through the server relay and
+	// look for another peer with a new tmp
+	async fn relay_hello(socks: &mut Sockets<'_>, addr: SocketAddr, sender: Sender, html: &Html) -> Result<(), String> {
+		let id = socks.network.as_ref().ok_or("Should have a network")?.id;
+		if let Some(Socket::WebRTC(socket)) = socks.tmp.socket.take() {
+			socket.delete();
+		}
+		socks.tmp.state = State::Disconnected(None);
+		html.fill(ids::TMP_PEER_ID, "None");
+		let hello = RTCData {
+			to: None,
+			id: 0,
+			timestamp: 0,
+			from: id,
+			content: RTCContent::Hello
+		};
+		socks.server.send(Data::WsData(WebSocketData::Relay(hello.into_u8()?, addr)));
+		socks.relaying.insert(addr);
+		html.chat_info("Cannot reach the peer directly, asking the server to relay...");
+		Event::new_tmp(socks, sender, html).await
+	}
+
+	async fn relay(socks: &mut Sockets<'_>, data: Vec<u8>, addr: SocketAddr, sender: Sender, html: &Html) -> Result<(), String> {
+		let data = RTCData::from_u8(data)?;
+		// a relayed link is a link like the others, the signaling for us included
+		if let Some(id) = socks.network.as_ref().ok_or("Should have a network")?.relayed(addr) {
+			return Event::rtc_message(socks, id, data, sender, html).await;
+		}
+		match data.content {
+			RTCContent::Hello => {
+				if socks.tmp.is_locked(Some(addr)) { // the peer gave up first
+					Event::relay_hello(socks, addr, sender, html).await?;
+				}
+				// only from the peer of a handshake that failed
+				if !socks.relaying.remove(&addr) {
+					return Err(format!("Unsolicited hello relayed from {}", addr));
+				}
+				let server = socks.server.clone();
+				let network = socks.network.as_mut().ok_or("Should have a network")?;
+				network.insert_relay(addr, server, data.from);
+				html.chat_info(format!("Connection relayed by the server with {}", data.from.to_name()).as_str());
+				Ok(())
+			},
+			_ => Err(format!("Relayed data from an unknown peer: {}", addr))
+		}
+	}
+
+	fn relay_closed(socks: &mut Sockets, addr: SocketAddr, html: &Html) -> Result<(), String> {
+		socks.relaying.remove(&addr);
+		let network = socks.network.as_mut().ok_or("Should have a network")?;
+		html.chat_info("The server cannot relay this connection");
+		match network.relayed(addr) {
+			Some(id) => network.remove(id),
+			None => Ok(())
+		}
+	}
+
 	fn dcobj(socks: &mut Sockets, dc: RtcDataChannel, sender: Sender) -> Result<(), String> {
 		let id = socks.network.as_ref().ok_or("Should have a network")?.id;
 		match (socks.tmp.state, &mut socks.tmp.socket) {
@@ -100,12 +290,17 @@ impl Event {
 				if socks.tmp.is_connected() || socks.tmp.is_locked(None) { // rly None ?
 					return Err("Icoming SDP but tmp socket already taken and active (should be moved to a non temporary place".to_string());
 				}
+				// WebRTC already failed with it
+				if socks.network.as_ref().and_then(|network| network.relayed(addr)).is_some() {
+					return Err(format!("Offer from {} but the server already relays it", addr));
+				}
 				if let Some(Socket::WebRTC(socket)) = &mut socks.tmp.socket {
-					socket.offer(&socks.server, &sdp, addr, sender).await.map_err(|e| format!("{:?}", e))?;
+					socket.offer(Signaling::Server(socks.server.clone(), addr), &sdp, sender).await.map_err(|e| format!("{:?}", e))?;
 					html.fill(ids::TMP_PEER_ID, "Connecting...");
 				} else {
-					let mut socket = RTCSocket::new(&socks.server, sender.clone(), html, false).await.map_err(|e| format!("{:?}", e))?;
-					socket.offer(&socks.server, &sdp, addr, sender).await.map_err(|e| format!("{:?}", e))?;
+					let ice = socks.ice_servers()?;
+					let mut socket = RTCSocket::new(&socks.server, &ice, sender.clone(), html, false).await.map_err(|e| format!("{:?}", e))?;
+					socket.offer(Signaling::Server(socks.server.clone(), addr), &sdp, sender).await.map_err(|e| format!("{:?}", e))?;
 					socks.tmp.socket = Some(Socket::WebRTC(socket));
 					html.fill(ids::TMP_PEER_ID, "Connecting...");
 				}
@@ -118,7 +313,7 @@ impl Event {
 					Err("The socket is locked".to_string())
 				}
 				else if let Some(Socket::WebRTC(socket)) = &mut socks.tmp.socket {
-					socket.answer(&socks.server, &sdp, addr, sender, id).await.map_err(|e| format!("{:?}", e))?;
+					socket.answer(Signaling::Server(socks.server.clone(), addr), &sdp, sender, id).await.map_err(|e| format!("{:?}", e))?;
 					socks.tmp.state = State::Locked(addr);
 					Ok(())
 				} else {
@@ -136,11 +331,57 @@ impl Event {
 				}
 			},
 
-			WebSocketData::Id(Some(id)) => {
-				if socks.network.is_none() {
-					socks.network = Some(Network::new(html, id));
-					html.fill(ids::ID_FIELD_ID, &id.to_name());
-					html.chat_info(&format!("Your id is: {}", id.0));
+			WebSocketData::IceServers(ice) => {
+				let first = socks.ice_servers.is_none();
+				socks.ice_servers = Some(ice);
+				if first && socks.tmp.is_disconnected() { // add the others
+					Event::new_tmp(socks, sender, html).await
+				} else { Ok(()) }
+			},
+			WebSocketData::TurnCredentials(Some(creds)) => {
+				let expired = socks.turn_expired();
+				socks.turn = Some(Turn::new(creds));
+				// new_tmp was waiting for them
+				if expired && socks.tmp.is_disconnected() && socks.tmp.socket.is_none() {
+					Event::new_tmp(socks, sender, html).await
+				} else { Ok(()) }
+			},
+			WebSocketData::Relay(data, addr) => Event::relay(socks, data, addr, sender, html).await,
+			WebSocketData::RelayClosed(addr) => Event::relay_closed(socks, addr, html),
+			WebSocketData::GoingAway(window) => {
+				// spread the reconnections of every client over the window
+				let delay = js_sys::Math::random() * window.saturating_mul(1000).min(GOING_AWAY_MAX) as f64;
+				socks.reconnect_in = Some(delay as u64);
+				html.chat_info("The server is shutting down");
+				Ok(())
+			},
+			WebSocketData::Notice(notice) => {
+				html.chat_private("Server", &notice);
+				Ok(())
+			},
+			WebSocketData::Neighbourhood(neighbourhood) => {
+				socks.neighbourhood = neighbourhood;
+				match &mut socks.network {
+					Some(network) if *network.topology() != neighbourhood => network.set_topology(neighbourhood),
+					_ => ()
+				}
+				Ok(())
+			},
+			WebSocketData::Session(id, token) => {
+				socks.session = Some(token);
+				match &socks.network {
+					Some(network) if network.id == id => (),
+					Some(_) => {
+						// the peers were chosen for the old id
+						html.chat_info(&format!("The session expired, your new id is: {}", id.0));
+						socks.network = Some(Network::new(html, id, socks.neighbourhood, sender.clone()));
+						html.fill(ids::ID_FIELD_ID, &id.to_name());
+					},
+					None => {
+						socks.network = Some(Network::new(html, id, socks.neighbourhood, sender.clone()));
+						html.fill(ids::ID_FIELD_ID, &id.to_name());
+						html.chat_info(&format!("Your id is: {}", id.0));
+					}
 				}
 				Ok(())
 			}
@@ -151,17 +392,34 @@ impl Event {
 	async fn server_connected(socks: &mut Sockets<'_>, sender: Sender, html: &Html) -> Result<(), String> {
 		html.chat_info("Connected to the server!");
 		socks.server.state = State::Connected(crate::time_now());
-		// Ask or set the id server side
-		socks.server.send(Data::WsData(WebSocketData::Id(socks.network.as_ref().and_then(|net| Some(net.id)))));
-		if socks.tmp.is_disconnected() { // add the others
-			match RTCSocket::new(&socks.server, sender, html, true).await {
-				Ok(socket) => { socks.tmp.socket = Some(Socket::WebRTC(socket)); Ok(()) },
-				Err(e) => Err(format!("Error while creating socket: {:?}", e))
-			}
+		html.fill(ids::SERVER_STATUS_ID, &socks.status());
+		socks.attempts = 0;
+		// a parked handshake that did not complete needs the new connection for its signaling
+		if let Some(Socket::WebRTC(socket)) = socks.tmp.socket.take() {
+			socket.delete();
+			socks.tmp.state = State::Disconnected(None);
+			html.fill(ids::TMP_PEER_ID, "None");
+		}
+		// Get an id, the same as before if the session is still valid
+		socks.server.send(Data::WsData(WebSocketData::Resume(socks.session.clone())));
+		// Wait for the ice servers before looking for a peer
+		if socks.ice_servers.is_some() && socks.tmp.is_disconnected() { // add the others
+			Event::new_tmp(socks, sender, html).await
 		} else { Ok(()) }
 	}
 
-	fn html(socks: &Sockets, id: String, msg: JsValue, html: &Html) -> Result<(), String> {
+	async fn new_tmp(socks: &mut Sockets<'_>, sender: Sender, html: &Html) -> Result<(), String> {
+		let ice = socks.ice_servers()?;
+		if socks.turn_expired() { // wait for the new credentials
+			return Ok(());
+		}
+		match RTCSocket::new(&socks.server, &ice, sender, html, true).await {
+			Ok(socket) => { socks.tmp.socket = Some(Socket::WebRTC(socket)); Ok(()) },
+			Err(e) => Err(format!("Error while creating socket: {:?}", e))
+		}
+	}
+
+	fn html(socks: &Sockets, id: String, msg: JsValue, html: &Html, sender: Sender) -> Result<(), String> {
 		let network = socks.network.as_ref().ok_or("You are not connected to the network")?;
 		match id.as_str() {
 			ids::BUTTON_SEND_MESSAGE => {
@@ -169,15 +427,15 @@ impl Event {
 				let msg = msg.trim();
 				if msg.is_empty() { return Ok(()) }
 				html.set_input_value(ids::MESSAGE_FIELD_ID, "");
+				// `/connect <name>` open a connection signaled by the peers
+				if let Some(name) = msg.strip_prefix("/connect ") {
+					let name = name.trim();
+					let id = name.parse::<Id>().map_err(|e| format!("Invalid name {}: {}", name, e))?;
+					sender.send(Event::OverlayConnect(id));
+					return Ok(());
+				}
 				html.chat_msg("Me", msg);
-				let msg = RTCData {
-					to: None,
-					id: 0,
-					timestamp: 0,
-					from: network.id,
-					content: RTCContent::Message(msg.to_string())
-				};
-				network.send(&msg, network.id);
+				network.broadcast(RTCContent::Message(msg.to_string()))?;
 				// let rsp = WebSocketData::Message(msg);
 				// socks.server.send(Data::WsData(rsp));
 				// socks.tmp.send(Data::RtcData(msg.to_string()));
@@ -189,9 +447,27 @@ impl Event {
 	}
 
 	fn server_disconnect(socks: &mut Sockets, html: &Html, sender: Sender) -> Result<(), String> {
+		if let Some(network) = socks.network.as_mut() {
+			network.remove_relays();
+		}
 		if let Some(Socket::WebSocket(server)) = &socks.server.socket {
 			server.delete();
 		}
+		// Degraded mode: the established peers do not need the server, the
+		// pending handshake is parked, it still completes if its signaling was done
+		socks.server.state = State::Disconnected(Some(crate::time_now()));
+		if socks.server.socket.is_some() && socks.attempts == 0 {
+			html.chat_info("Server offline, the chat continues with the connected peers");
+		}
+		// the server asked for a delay, otherwise back off a little more on every failure
+		let delay = socks.reconnect_in.take().unwrap_or_else(|| backoff(socks.attempts));
+		socks.attempts += 1;
+		html.fill(ids::SERVER_STATUS_ID, &format!("{}, reconnecting in {}s (attempt {})", socks.status(), delay.div_ceil(1000), socks.attempts));
+		sender.send_later(Event::ServerReconnect, delay);
+		Ok(())
+	}
+
+	fn server_reconnect(socks: &mut Sockets, html: &Html, sender: Sender) -> Result<(), String> {
 		let socket = WebSocket::new(sender, html)?;
 		socks.server.socket = Some(Socket::WebSocket(socket));
 		Ok(())
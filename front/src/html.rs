@@ -7,15 +7,14 @@ use crate::Sender;
 
 // Html element ids
 pub mod ids {
-	pub const BUTTON_SEND_MESSAGE: &'static str = "send_message";
-	pub const MESSAGE_FIELD_ID: &'static str = "message_field";
-	pub const MESSAGE_BOX_ID: &'static str = "message_box";
-	pub const ID_FIELD_ID: &'static str = "id_field";
-	pub const TMP_PEER_ID: &'static str = "tmp_peer";
-	pub const TOP_PEER_ID: &'static str = "top_peer";
-	pub const LEFT_PEER_ID: &'static str = "left_peer";
-	pub const RIGHT_PEER_ID: &'static str = "right_peer";
-	pub const CACHE_PEER_ID: &'static str = "cache_peer";
+	pub const BUTTON_SEND_MESSAGE: &str = "send_message";
+	pub const MESSAGE_FIELD_ID: &str = "message_field";
+	pub const MESSAGE_BOX_ID: &str = "message_box";
+	pub const ID_FIELD_ID: &str = "id_field";
+	pub const SERVER_STATUS_ID: &str = "server_status";
+	pub const TMP_PEER_ID: &str = "tmp_peer";
+	pub const NEIGHBOUR_PEERS_ID: &str = "neighbour_peers";
+	pub const CACHE_PEER_ID: &str = "cache_peer";
 }
 
 // TODO: global input hashmap (gota go fast)
@@ -48,10 +47,9 @@ impl Html {
 			(ids::MESSAGE_BOX_ID, false),
 			(ids::BUTTON_SEND_MESSAGE, true),
 			(ids::ID_FIELD_ID, false),
+			(ids::SERVER_STATUS_ID, false),
 			(ids::TMP_PEER_ID, false),
-			(ids::TOP_PEER_ID, false),
-			(ids::LEFT_PEER_ID, false),
-			(ids::RIGHT_PEER_ID, false),
+			(ids::NEIGHBOUR_PEERS_ID, false),
 			(ids::CACHE_PEER_ID, false)
 		];
 		for (id, click) in ids.iter() {
@@ -63,22 +61,21 @@ impl Html {
 			}
 		}
 
-		let ret = Self {
+		Self {
 			elements,
 			window,
 			document
-		};
-		ret
+		}
 	}
 
 	fn chat_bottom_scroll(&self) {
-		if let Some(elem) = self.elements.get(&ids::MESSAGE_BOX_ID.to_string()) {
+		if let Some(elem) = self.elements.get(ids::MESSAGE_BOX_ID) {
 			elem.set_scroll_top(elem.scroll_height());
 		}
 	}
 
 	pub fn get_input_value(&self, id: &str) -> String {
-		if let Some(elem) = self.elements.get(&id.to_string()) {
+		if let Some(elem) = self.elements.get(id) {
 			let input = elem
 				.dyn_ref::<HtmlInputElement>()
 				.expect("Input is not an input");
@@ -87,7 +84,7 @@ impl Html {
 	}
 	
 	pub fn set_input_value(&self, id: &str, value: &str) {
-		if let Some(elem) = self.elements.get(&id.to_string()) {
+		if let Some(elem) = self.elements.get(id) {
 			let input = elem
 				.dyn_ref::<HtmlInputElement>()
 				.expect("Input is not an input");
@@ -96,17 +93,26 @@ impl Html {
 	}
 
 	pub fn append(&self, id: &str, value: &str) {
-		if let Some(elem) = self.elements.get(&id.to_string()) {
+		if let Some(elem) = self.elements.get(id) {
 			elem.insert_adjacent_html("beforeend", value).unwrap_or(());
 		}
 	}
 
 	pub fn fill(&self, id: &str, value: &str) {
-		if let Some(elem) = self.elements.get(&id.to_string()) {
+		if let Some(elem) = self.elements.get(id) {
 			elem.set_inner_html(value);
 		}
 	}
 
+	// Parameter of the page url
+	pub fn query(&self, key: &str) -> Option<String> {
+		let search = self.window.location().search().ok()?;
+		search.trim_start_matches('?')
+			.split('&')
+			.find_map(|param| param.strip_prefix(key)?.strip_prefix('='))
+			.map(str::to_string)
+	}
+
 	pub fn chat_msg(&self, user: &str, msg: &str) {
 		self.append(ids::MESSAGE_BOX_ID, format!("<p><b>{}: </b> {}</p>", user, msg).as_str());
 		self.chat_bottom_scroll();
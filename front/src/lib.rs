@@ -1,4 +1,5 @@
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 use futures::channel::mpsc::{ unbounded, UnboundedSender };
 use futures::stream::StreamExt;
 use js_sys::Date;
@@ -40,9 +41,18 @@ pub struct Sender(UnboundedSender<Event>);
 
 impl Sender {
 	pub fn send(&self, ev: Event) {
-		match self.0.unbounded_send(ev) {
-			Err(e) => console_log!("Local event send error: {:?}", e),
-			_ => ()
+		if let Err(e) = self.0.unbounded_send(ev) {
+			console_log!("Local event send error: {:?}", e)
+		}
+	}
+
+	// Send the event in `ms` milliseconds
+	pub fn send_later(&self, ev: Event, ms: u64) {
+		let sender = self.clone();
+		let cb = Closure::once_into_js(move || sender.send(ev));
+		let window = web_sys::window().expect("Cannot get the window object");
+		if let Err(e) = window.set_timeout_with_callback_and_timeout_and_arguments_0(cb.unchecked_ref(), ms as i32) {
+			console_log!("Cannot set a timeout: {:?}", e)
 		}
 	}
 }
@@ -53,6 +63,7 @@ async fn main_loop() {
 	let sender = Sender(sender);
 	let html = Html::new(sender.clone());
 	sender.send(Event::ServerDisconnect);
+	sender.send_later(Event::Gossip, p2p::GOSSIP_INTERVAL);
 	let mut socks = streams::Sockets::default();
 	/*
 	for_each not working with async block inside we got:receiver
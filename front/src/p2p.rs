@@ -1,28 +1,61 @@
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::ops::{ Deref, DerefMut };
 use wasm_bindgen::{ JsValue, JsCast };
 use wasm_bindgen::closure::Closure;
 use js_sys::ArrayBuffer;
-use crossplatform::id::{ Id, Axe };
-use crossplatform::proto_rtc::{ RTCData, RTCContent };
+use crossplatform::id::Id;
+use crossplatform::topology::Neighbourhood;
+use crossplatform::proto_rtc::RTCData;
+use crossplatform::overlay::{ Overlay, PeerLink, Transport, Ui };
 use crate::html::{ Html, ids };
 use crate::webrtc::RTCSocket;
+use crate::streams::{ Pstream, Data };
 use crate::event::Event;
 use crate::{ log, console_log };
 use crate::{ Sender };
+use crossplatform::proto_ws::WebSocketData;
 use web_sys::{
 	MessageEvent,
 };
 
-#[derive(Debug)]
-struct Peer {
-	id: Id,
-	socket: RTCSocket
-	// Id of his connections maybe
+pub use crossplatform::overlay::GOSSIP_INTERVAL;
+
+const OVERLAY_TIMEOUT: u64 = 30_000; // ms, for a connection signaled through the overlay
+
+pub enum Link {
+	Rtc(RTCSocket),
+	Relay(SocketAddr, Pstream) // through the server when WebRTC failed
 }
 
-impl Peer {
-	pub fn send(&self, from: Id, data_from: Id, data: &[u8]) {
-		if self.id != from && self.id != data_from {
-			self.socket.send(data);
+impl std::fmt::Debug for Link {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Link::Rtc(socket) => write!(f, "Rtc({:?})", socket),
+			Link::Relay(addr, _) => write!(f, "Relay({})", addr)
+		}
+	}
+}
+
+impl PeerLink for Link {
+	fn send(&self, data: &[u8]) {
+		match self {
+			Link::Rtc(socket) => socket.send(data),
+			Link::Relay(addr, server) => server.send(Data::WsData(WebSocketData::Relay(data.to_vec(), *addr)))
+		}
+	}
+
+	fn relayed(&self) -> Option<SocketAddr> {
+		match self {
+			Link::Relay(addr, _) => Some(*addr),
+			_ => None
+		}
+	}
+
+	// a relay has nothing to close
+	fn close(&self) {
+		if let Link::Rtc(socket) = self {
+			socket.delete();
 		}
 	}
 }
@@ -38,8 +71,11 @@ fn new_cb(id: Id, sender: Sender, socket: &mut RTCSocket)
 		let ev = MessageEvent::from(ev);
 		if let Ok(abuf) =  ev.data().dyn_into::<ArrayBuffer>() {
 			let array = js_sys::Uint8Array::new(&abuf).to_vec();
-			let msg = RTCData::from_u8(array).expect("Invalid incomming");
-			sender.send(Event::RTCMessage(id, msg));
+			// a malformed frame of a peer is dropped, not the page
+			match RTCData::from_u8(array) {
+				Ok(msg) => sender.send(Event::RTCMessage(id, msg)),
+				Err(e) => console_log!("Invalid frame from {}: {}", id.to_name(), e)
+			}
 		} else {
 			console_log!("Invalid: {:?}", ev);
 		}
@@ -49,156 +85,113 @@ fn new_cb(id: Id, sender: Sender, socket: &mut RTCSocket)
 	socket.cbs.push(onmessage_callback);
 }
 
-#[derive(Debug)]
-pub struct Network<'a> {
-	pub id: Id, // Remove Option ?
-	top: Option<Peer>,
-	left: Option<Peer>,
-	right: Option<Peer>,
-	peer_cache: Vec<Peer>,
-	html: &'a Html
+// Connects through the overlay, the handshakes wait here until the data channel
+// opens, with the time they started
+pub struct WebTransport {
+	sender: Sender,
+	pending: HashMap<Id, (RTCSocket, u64)>
 }
 
-impl<'a> Network<'a> {
-	pub fn new(html: &'a Html, id: Id) -> Self {
-		Network {
-			id,
-			top: None,
-			left: None,
-			right: None,
-			peer_cache: vec!(),
-			html
+impl Transport for WebTransport {
+	type Link = Link;
+
+	fn connect(&self, id: Id) {
+		self.sender.send(Event::OverlayConnect(id));
+	}
+
+	fn is_pending(&self, id: Id) -> bool {
+		self.pending.contains_key(&id)
+	}
+}
+
+impl Ui for &Html {
+	fn chat_msg(&self, from: &str, msg: &str) {
+		Html::chat_msg(self, from, msg)
+	}
+
+	fn chat_private(&self, from: &str, msg: &str) {
+		Html::chat_private(self, from, msg)
+	}
+
+	fn chat_info(&self, msg: &str) {
+		Html::chat_info(self, msg)
+	}
+
+	fn neighbours(&self, neighbours: &[(String, Id)], cache: &[Id]) {
+		if neighbours.is_empty() {
+			self.fill(ids::NEIGHBOUR_PEERS_ID, "<p><b>Neighbours:&nbsp;</b>None</p>");
+		} else {
+			self.fill(ids::NEIGHBOUR_PEERS_ID, "");
 		}
+		neighbours.iter().for_each(|(label, id)| {
+			self.append(ids::NEIGHBOUR_PEERS_ID, format!("<p><b>{}:&nbsp;</b><span>{}</span></p>", label, id.to_name()).as_str())
+		});
+		self.fill(ids::CACHE_PEER_ID, "");
+		cache.iter().for_each(|id| {
+			self.append(ids::CACHE_PEER_ID, format!("<span>{}</span>", id.to_name()).as_str())
+		})
 	}
+}
+
+// The overlay with WebRTC data channels and server relays as links
+pub struct Network<'a>(Overlay<WebTransport, &'a Html>);
 
-	pub fn insert(&mut self, mut socket: RTCSocket, id: Id, sender: Sender) -> Option<()> {
-		let distance = self.id.distance(&id);
+impl<'a> Deref for Network<'a> {
+	type Target = Overlay<WebTransport, &'a Html>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl<'a> DerefMut for Network<'a> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.0
+	}
+}
+
+impl<'a> Network<'a> {
+	pub fn new(html: &'a Html, id: Id, neighbourhood: Neighbourhood, sender: Sender) -> Self {
+		Network(Overlay::new(id, neighbourhood, WebTransport { sender, pending: HashMap::new() }, html))
+	}
+
+	pub fn insert(&mut self, mut socket: RTCSocket, id: Id, sender: Sender) {
 		new_cb(id, sender, &mut socket);
-		let peer = Peer { id, socket };
-		match self.id.get_axe(id) {
-			Axe::Top => {
-				match &self.top {
-					Some(top) => {
-						if self.id.distance(&top.id) > distance {
-							let peer = Some(peer);
-							self.peer_cache.push(std::mem::replace(&mut self.top, peer)?);
-						} else {
-							self.peer_cache.push(peer)
-						}
-					},
-					None => self.top = Some(peer)
-				}
-			},
-			Axe::Right => {
-				match &self.right {
-					Some(right) => {
-						if self.id.distance(&right.id) > distance {
-							let peer = Some(peer);
-							self.peer_cache.push(std::mem::replace(&mut self.right, peer)?);
-						} else {
-							self.peer_cache.push(peer)
-						}
-					},
-					None => self.right = Some(peer)
-				}
-			},
-			Axe::Left => {
-				match &self.left {
-					Some(left) => {
-						if self.id.distance(&left.id) > distance {
-							let peer = Some(peer);
-							self.peer_cache.push(std::mem::replace(&mut self.left, peer)?);
-						} else {
-							self.peer_cache.push(peer)
-						}
-					},
-					None => self.left = Some(peer)
-				}
-			}
-		};
-		self.refresh_html();
-		Some(())
-	}
-
-	pub fn process(&self, data: &RTCData, from: Id) -> Result<(), String> {
-		match &data.content {
-			RTCContent::Message(msg) => {
-				if let Some(target) = data.to {
-					if target == self.id {
-						self.html.chat_private(data.from.to_name().as_str(), msg.as_str())
-					} else {
-						// self.send_to() 
-						self.send(data, from);
-					}
-				} else {
-					self.html.chat_msg(data.from.to_name().as_str(), msg.as_str());
-					self.send(data, from);
-				}
-			},
-			RTCContent::Received(_id, _timestamp) => { }
-			RTCContent::NotFound => { },
-		}
-		Ok(())
+		self.0.insert(Link::Rtc(socket), id)
 	}
 
-	pub fn refresh_html(&self) {
-		self.html.fill(ids::TOP_PEER_ID, self.top.as_ref().and_then(|a| Some(a.id.to_name())).unwrap_or("None".to_string()).as_str());
-		self.html.fill(ids::LEFT_PEER_ID, self.left.as_ref().and_then(|a| Some(a.id.to_name())).unwrap_or("None".to_string()).as_str());
-		self.html.fill(ids::RIGHT_PEER_ID, self.right.as_ref().and_then(|a| Some(a.id.to_name())).unwrap_or("None".to_string()).as_str());
-		self.html.fill(ids::CACHE_PEER_ID, "");
-		self.peer_cache.iter().for_each(|peer| {
-			self.html.append(ids::CACHE_PEER_ID, format!("<span>{}</span>", peer.id.to_name()).as_str())
-		})
+	pub fn insert_relay(&mut self, addr: SocketAddr, server: Pstream, id: Id) {
+		self.0.insert(Link::Relay(addr, server), id)
 	}
 
-	pub fn remove(&mut self, id: Id) -> Result<(), String>{
-		// TODO: Replace one of the side from a peer from the cache
-		if let Some(top) = &self.top {
-			if top.id == id {
-				self.top = None
-			}
-		} else if let Some(right) = &self.right {
-			if right.id == id {
-				self.right = None
-			}
-		} else if let Some(left) = &self.left {
-			if left.id == id {
-				self.left = None
-			}
-		} else {
-			if let Some(index) = self.peer_cache.iter().position(|x| x.id == id) {
-				// self.conns[index].disconnect();
-				self.peer_cache.remove(index);
-			} else {
-				return Err("Unknow Peer as disconnected".to_string());
-			}
+	// The server is gone, so are the relayed links
+	pub fn remove_relays(&mut self) {
+		self.remove_links(|link| matches!(link, Link::Relay(..)))
+	}
+
+	pub fn pending(&mut self, id: Id) -> Option<&mut RTCSocket> {
+		self.transport_mut().pending.get_mut(&id).map(|(socket, _)| socket)
+	}
+
+	// Given up with Event::OverlayTimeout if the channel is not open in time
+	pub fn add_pending(&mut self, id: Id, socket: RTCSocket) {
+		let started = crate::time_now();
+		let transport = self.transport_mut();
+		if let Some((old, _)) = transport.pending.insert(id, (socket, started)) {
+			old.delete();
 		}
-		self.html.chat_info(format!("{} as disconnected.", id.to_name()).as_str());
-		self.refresh_html();
-		Ok(())
+		transport.sender.send_later(Event::OverlayTimeout(id, started), OVERLAY_TIMEOUT);
 	}
 
-	pub fn send(&self, data: &RTCData, from: Id) {
-		// TODO: put the message in memory to not send 2 time the same message
-		if let Some(_id) = data.to {
-			// Send to specifiq user
-		} else {
-			// Send to all users
-			let data_from = data.from;
-			let data = data.into_u8().expect("cannot serialize");
-			let data = data.as_slice();
-			if let Some(top) = &self.top {
-				top.send(from, data_from, data);
-			}
-			if let Some(right) = &self.right {
-				right.send(from, data_from, data);
-			}
-			if let Some(left) = &self.left {
-				left.send(from, data_from, data);
-			}
-			self.peer_cache.iter().for_each(|peer| {
-				peer.send(from, data_from, data);
-			})
+	pub fn take_pending(&mut self, id: Id) -> Option<RTCSocket> {
+		self.transport_mut().pending.remove(&id).map(|(socket, _)| socket)
+	}
+
+	// The handshake with `id` if it is still the one started at `started`
+	pub fn take_expired(&mut self, id: Id, started: u64) -> Option<RTCSocket> {
+		match self.transport().pending.get(&id) {
+			Some((_, pending)) if *pending == started => self.take_pending(id),
+			_ => None
 		}
 	}
-}
\ No newline at end of file
+}
@@ -1,5 +1,7 @@
 use std::net::SocketAddr;
-use crossplatform::proto_ws::WebSocketData;
+use std::collections::HashSet;
+use crossplatform::proto_ws::{ WebSocketData, IceServer, TurnCredentials };
+use crossplatform::topology::Neighbourhood;
 
 use crate::{ log, console_log };
 use crate::webrtc::RTCSocket;
@@ -62,10 +64,7 @@ impl Pstream {
 	}
 
 	pub fn is_disconnected(&self) -> bool {
-		match self.state {
-			State::Disconnected(_) => true,
-			_ => false
-		}
+		matches!(self.state, State::Disconnected(_))
 	}
 
 	pub fn is_waiting(&self) -> bool {
@@ -89,12 +88,48 @@ impl Pstream {
 	}
 }
 
+const BACKOFF_BASE: u64 = 500; // ms
+const BACKOFF_MAX: u64 = 30_000;
+
+// Exponential backoff with full jitter: random delay up to base * 2^attempt
+pub fn backoff(attempt: u32) -> u64 {
+	let max = BACKOFF_BASE.saturating_mul(1 << attempt.min(16)).min(BACKOFF_MAX);
+	(js_sys::Math::random() * max as f64) as u64
+}
+
+// Time limited TURN server, timestamps are local (ms)
+pub struct Turn {
+	pub server: IceServer,
+	pub refresh_at: u64,
+	pub expires: u64,
+	pub requested: bool // fresh credentials have been asked
+}
+
+impl Turn {
+	pub fn new(creds: TurnCredentials) -> Self {
+		let now = crate::time_now();
+		Turn {
+			server: creds.server,
+			refresh_at: now + creds.ttl * 800, // ask for new ones at 80% of the ttl
+			expires: now + creds.ttl * 1000,
+			requested: false
+		}
+	}
+}
+
 // TODO all mutex
 pub struct Sockets<'a> {
 	pub server: Pstream,
 	pub network: Option<Network<'a>>,
-	pub tmp: Pstream // should be in Network
+	pub tmp: Pstream, // should be in Network
 	// TODO: Multiples tmp?
+	pub ice_servers: Option<Vec<IceServer>>, // None until the server send them
+	pub turn: Option<Turn>,
+	pub relaying: HashSet<SocketAddr>, // failed handshakes we said hello to, waiting for the hello of the peer
+	pub reconnect_in: Option<u64>, // ms, the server is going away
+	pub session: Option<String>, // resume token, to get the same id after a reconnection
+	pub neighbourhood: Neighbourhood, // of the network, announced by the server
+	pub attempts: u32 // failed connections to the server in a row
 }
 
 impl<'a> Sockets<'a> {
@@ -105,7 +140,46 @@ impl<'a> Sockets<'a> {
 			// dright: None,
 			tmp: Pstream { state: State::Disconnected(None), socket: None},
 			network: None,
+			ice_servers: None,
+			turn: None,
+			relaying: HashSet::new(),
+			reconnect_in: None,
+			session: None,
+			neighbourhood: Neighbourhood::default(),
+			attempts: 0,
 			// dleft: None
 		}
 	}
+
+	// Ice servers for a new RtcPeerConnection, ask for new TURN credentials when they are getting old
+	pub fn ice_servers(&mut self) -> Result<Vec<IceServer>, String> {
+		let mut ice = self.ice_servers.clone().ok_or("No ice servers")?;
+		if let Some(turn) = &mut self.turn {
+			let now = crate::time_now();
+			if now >= turn.refresh_at && !turn.requested {
+				self.server.send(Data::WsData(WebSocketData::TurnCredentials(None)));
+				turn.requested = true;
+			}
+			if now < turn.expires {
+				ice.push(turn.server.clone());
+			}
+		}
+		Ok(ice)
+	}
+
+	// The chat keeps working through the peers while the server is offline
+	pub fn status(&self) -> String {
+		let peers = self.network.as_ref().map_or(0, |network| network.len());
+		match self.server.state {
+			State::Connected(_) => format!("connected, {} peers", peers),
+			_ => format!("offline, {} peers connected", peers)
+		}
+	}
+
+	pub fn turn_expired(&self) -> bool {
+		match &self.turn {
+			Some(turn) => crate::time_now() >= turn.expires,
+			None => false
+		}
+	}
 }
\ No newline at end of file
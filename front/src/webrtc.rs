@@ -1,7 +1,7 @@
 use std::net::SocketAddr;
 use wasm_bindgen::{ JsValue, JsCast };
 use wasm_bindgen::closure::Closure;
-use js_sys::{ JSON, Reflect };
+use js_sys::{ Array, Object, Reflect };
 use web_sys::{
 	MessageEvent,
 	RtcSdpType,
@@ -13,17 +13,89 @@ use web_sys::{
 	RtcDataChannelEvent,
 	RtcSessionDescriptionInit,
 	RtcDataChannel,
-	RtcDataChannelType
+	RtcDataChannelType,
+	RtcIceConnectionState,
+	RtcDataChannelState
 };
 use wasm_bindgen_futures::JsFuture;
-use crossplatform::proto_ws::{ WebSocketData, IceCandidateStruct };
+use crossplatform::proto_ws::{ WebSocketData, IceCandidateStruct, IceServer };
 use crossplatform::id::Id;
+use crossplatform::proto_rtc::RTCContent;
 use crate::{ log, console_log, Sender };
 use crate::streams::{ Data, Pstream };
 use crate::event::Event;
 use crate::html::Html;
 
-const ICE_SERVERS: &str = "[{\"urls\": \"stun:stun.l.google.com:19302\"}]";
+// Build the RTCIceServer array, the list come from the server
+fn ice_servers(servers: &[IceServer]) -> Result<Array, JsValue> {
+	let array = Array::new();
+	for server in servers.iter() {
+		let obj = Object::new();
+		let urls = server.urls.iter().map(|url| JsValue::from_str(url)).collect::<Array>();
+		Reflect::set(&obj, &JsValue::from_str("urls"), &urls)?;
+		if let Some(username) = &server.username {
+			Reflect::set(&obj, &JsValue::from_str("username"), &JsValue::from_str(username))?;
+		}
+		if let Some(credential) = &server.credential {
+			Reflect::set(&obj, &JsValue::from_str("credential"), &JsValue::from_str(credential))?;
+		}
+		array.push(&obj);
+	}
+	Ok(array)
+}
+
+// Where the answer and the ice candidates go
+#[derive(Clone)]
+pub enum Signaling {
+	Server(Pstream, SocketAddr), // brokered by the server
+	Overlay(Sender, Id) // routed by the peers up to this id
+}
+
+impl Signaling {
+	fn answer(&self, sdp: String) {
+		match self {
+			Signaling::Server(server, addr) => server.send(Data::WsData(WebSocketData::AnswerSDP(sdp, *addr))),
+			Signaling::Overlay(sender, id) => sender.send(Event::Signal(*id, RTCContent::Answer(sdp)))
+		}
+	}
+
+	fn ice_candidate(&self, candidate: IceCandidateStruct) {
+		match self {
+			Signaling::Server(server, addr) => server.send(Data::WsData(WebSocketData::IceCandidate(candidate, *addr))),
+			Signaling::Overlay(sender, id) => sender.send(Event::Signal(*id, RTCContent::IceCandidate(candidate)))
+		}
+	}
+
+	fn on_ice_candidate(&self, conn: &RtcPeerConnection, cbs: &mut Vec<Closure<dyn FnMut (JsValue)>>) {
+		let signaling = self.clone();
+		let cb = Closure::wrap(Box::new(move |ev: JsValue| {
+			if let Some(candidate) = RtcPeerConnectionIceEvent::from(ev).candidate() {
+				signaling.ice_candidate(IceCandidateStruct {
+					candidate: candidate.candidate(),
+					sdp_mid: candidate.sdp_mid(),
+					sdp_m_line_index: candidate.sdp_m_line_index()
+				});
+			}
+		}) as Box<dyn FnMut(JsValue)>);
+		conn.set_onicecandidate(Some(cb.as_ref().unchecked_ref()));
+		cbs.push(cb);
+	}
+
+	// The server relay is only a fallback for the connections it brokered
+	fn on_ice_failed(&self, conn: &RtcPeerConnection, cbs: &mut Vec<Closure<dyn FnMut (JsValue)>>) {
+		if let Signaling::Overlay(sender, id) = self {
+			let (sender, id) = (sender.clone(), *id);
+			let conn_clone = conn.clone();
+			let cb = Closure::wrap(Box::new(move |_ev: JsValue| {
+				if conn_clone.ice_connection_state() == RtcIceConnectionState::Failed {
+					sender.send(Event::OverlayFailed(id));
+				}
+			}) as Box<dyn FnMut(JsValue)>);
+			conn.set_oniceconnectionstatechange(Some(cb.as_ref().unchecked_ref()));
+			cbs.push(cb);
+		}
+	}
+}
 
 #[derive(Debug)]
 pub struct RTCSocket {
@@ -50,14 +122,24 @@ impl RTCSocket {
 		}
 	}
 
-	pub async fn new(server: &Pstream, sender: Sender, html: &Html, should_send: bool) -> Result<Self, JsValue> {
+	pub async fn new(server: &Pstream, ice: &[IceServer], sender: Sender, html: &Html, should_send: bool) -> Result<Self, JsValue> {
 		let mut cbs = vec!();
 		/* Create the RtcPeerConnection struct */
 		let mut conf = RtcConfiguration::new();
-		let obj = JSON::parse(ICE_SERVERS)?;
-		conf.ice_servers(&obj);
+		conf.ice_servers(&ice_servers(ice)?.into());
 		let peer_connection = RtcPeerConnection::new_with_configuration(&conf)?;
 
+		/* Fallback on the server relay when ice cannot connect us */
+		let sender_cl = sender.clone();
+		let conn_clone = peer_connection.clone();
+		let oniceconnectionstatechange_callback = Closure::wrap(Box::new(move |_ev: JsValue| {
+			if conn_clone.ice_connection_state() == RtcIceConnectionState::Failed {
+				sender_cl.send(Event::IceFailed);
+			}
+		}) as Box<dyn FnMut(JsValue)>);
+		peer_connection.set_oniceconnectionstatechange(Some(oniceconnectionstatechange_callback.as_ref().unchecked_ref()));
+		cbs.push(oniceconnectionstatechange_callback);
+
 		/* Create the Data Channel */
 		let data_channel = peer_connection.create_data_channel("my-data-channel");
 		data_channel.set_binary_type(RtcDataChannelType::Arraybuffer);
@@ -65,11 +147,8 @@ impl RTCSocket {
 		let onmessage_callback =
 		Closure::wrap(
 			Box::new(move |ev: JsValue| {
-				match MessageEvent::from(ev).data().as_string() {
-					Some(message) => {
-						sender.send(Event::TmpId(message))
-					}
-					None => {}
+				if let Some(message) = MessageEvent::from(ev).data().as_string() {
+					sender.send(Event::TmpId(message))
 				}
 			}) as Box<dyn FnMut(JsValue)>,
 		);
@@ -94,10 +173,10 @@ impl RTCSocket {
 		})
 	}
 	
-	pub async fn offer(&mut self, server: &Pstream, sdp: &String, addr: SocketAddr, sender: Sender) -> Result<(), JsValue> {
+	pub async fn offer(&mut self, signaling: Signaling, sdp: &str, sender: Sender) -> Result<(), JsValue> {
 		/* Set Remote offer description */
 		let mut description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-		description.sdp(sdp.as_str());
+		description.sdp(sdp);
 		JsFuture::from(self.conn.set_remote_description(&description)).await?;
 
 		/* Create local answer */
@@ -106,60 +185,38 @@ impl RTCSocket {
 		let mut local_answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
 		local_answer.sdp(answer.as_str());
 		JsFuture::from(self.conn.set_local_description(&local_answer)).await?;
-		server.send(Data::WsData(WebSocketData::AnswerSDP(answer, addr)));
+		signaling.answer(answer);
 
 		/* Handle ice candidate */
-		let server = server.clone();
-		let cb = Closure::wrap(Box::new(move |ev: JsValue| {
-			match RtcPeerConnectionIceEvent::from(ev).candidate() {
-				Some(candidate) => {
-					let candidate = IceCandidateStruct {
-						candidate: candidate.candidate(),
-						sdp_mid: candidate.sdp_mid(),
-						sdp_m_line_index: candidate.sdp_m_line_index()
-					};
-					let message = WebSocketData::IceCandidate(candidate, addr);
-					server.send(Data::WsData(message));
-				},
-				None => {}
-			}
-		}) as Box<dyn FnMut(JsValue)>);
-		self.conn.set_onicecandidate(Some(cb.as_ref().unchecked_ref()));
-		self.cbs.push(cb);
+		signaling.on_ice_candidate(&self.conn, &mut self.cbs);
+		signaling.on_ice_failed(&self.conn, &mut self.cbs);
 
 		/* Handle OK connection */
 		let ondatachannel_callback = Closure::wrap(Box::new(move |ev: JsValue| {
 			let channel = RtcDataChannelEvent::from(ev).channel();
 			channel.set_binary_type(RtcDataChannelType::Arraybuffer);
-			sender.send(Event::DCObj(channel));
+			match &signaling {
+				Signaling::Server(..) => sender.send(Event::DCObj(channel)),
+				Signaling::Overlay(_, id) => sender.send(Event::OverlayChannel(*id, channel))
+			}
 		}) as Box<dyn FnMut(JsValue)>);
 		self.conn.set_ondatachannel(Some(ondatachannel_callback.as_ref().unchecked_ref()));
 		self.cbs.push(ondatachannel_callback);
 		Ok(())
 	}
 
-	pub async fn answer(&mut self, server: &Pstream, sdp: &String, addr: SocketAddr, sender: Sender, id: Id) -> Result<(), JsValue> {
+	pub async fn answer(&mut self, signaling: Signaling, sdp: &str, sender: Sender, id: Id) -> Result<(), JsValue> {
 		let mut answer_obj = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-		answer_obj.sdp(sdp.as_str());
+		answer_obj.sdp(sdp);
 		JsFuture::from(self.conn.set_remote_description(&answer_obj)).await?;
 		/* Handle ice candidate */
-		let server = server.clone();
-		let cb = Closure::wrap(Box::new(move |ev: JsValue| {
-			match RtcPeerConnectionIceEvent::from(ev).candidate() {
-				Some(candidate) => {
-					let candidate = IceCandidateStruct {
-						candidate: candidate.candidate(),
-						sdp_mid: candidate.sdp_mid(),
-						sdp_m_line_index: candidate.sdp_m_line_index()
-					};
-					let message = WebSocketData::IceCandidate(candidate, addr);
-					server.send(Data::WsData(message));
-				},
-				None => {}
-			}
-		}) as Box<dyn FnMut(JsValue)>);
-		self.conn.set_onicecandidate(Some(cb.as_ref().unchecked_ref()));
-		self.cbs.push(cb);
+		signaling.on_ice_candidate(&self.conn, &mut self.cbs);
+		signaling.on_ice_failed(&self.conn, &mut self.cbs);
+		// the overlay already know who is on the other side
+		if let Signaling::Overlay(_, peer) = signaling {
+			self.on_overlay_open(peer, sender);
+			return Ok(());
+		}
 		let sender_cl = sender.clone();
 		let cb = Closure::wrap(Box::new(move |_arg: JsValue| {
 			sender_cl.send(Event::RtcState(false));
@@ -179,10 +236,34 @@ impl RTCSocket {
 		Ok(())
 	}
 
+	// Channel of a connection signaled through the overlay, ready once open
+	pub fn on_overlay_open(&mut self, peer: Id, sender: Sender) {
+		if self.channel.ready_state() == RtcDataChannelState::Open {
+			sender.send(Event::OverlayOpen(peer));
+			return;
+		}
+		let cb = Closure::wrap(Box::new(move |_arg: JsValue| {
+			sender.send(Event::OverlayOpen(peer));
+		}) as Box<dyn FnMut(JsValue)>);
+		self.channel.set_onopen(Some(cb.as_ref().unchecked_ref()));
+		self.cbs.push(cb);
+	}
+
+	// The remote side created the channel
+	pub fn set_overlay_dc(&mut self, dc: RtcDataChannel, peer: Id, sender: Sender) {
+		self.channel.close();
+		self.channel = dc;
+		self.on_overlay_open(peer, sender);
+	}
+
+	pub fn local_sdp(&self) -> Option<String> {
+		self.conn.local_description().map(|description| description.sdp())
+	}
+
 	pub async fn ice_candidate(&self, candidate: &IceCandidateStruct) -> Result<(), JsValue> {
 		let mut icecandidate = RtcIceCandidateInit::new(candidate.candidate.as_str());
 		if let Some(sdp_mid) = &candidate.sdp_mid {
-			icecandidate.sdp_mid(Some(&sdp_mid.as_str()));
+			icecandidate.sdp_mid(Some(sdp_mid.as_str()));
 		}
 		icecandidate.sdp_m_line_index(candidate.sdp_m_line_index);
 	
@@ -197,9 +278,8 @@ impl RTCSocket {
 		let onmessage_callback =
 			Closure::wrap(
 				Box::new(move |ev: JsValue| {
-					match MessageEvent::from(ev).data().as_string() {
-						Some(message) => sender_cl.send(Event::TmpId(message)),
-						None => {}
+					if let Some(message) = MessageEvent::from(ev).data().as_string() {
+						sender_cl.send(Event::TmpId(message))
 					}
 				}) as Box<dyn FnMut(JsValue)>,
 			);
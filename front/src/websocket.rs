@@ -2,9 +2,9 @@ use web_sys::BinaryType;
 use wasm_bindgen::{ JsValue, JsCast };
 use wasm_bindgen::closure::Closure;
 use js_sys::Uint8Array;
-use crossplatform::proto_ws::WebSocketData;
+use crossplatform::proto_ws::{ WebSocketData, subprotocol };
 use web_sys::{ MessageEvent };
-use crate::html::Html;
+use crate::html::{ ids, Html };
 use crate::{ log, console_log, Sender };
 use crate::event::Event;
 
@@ -32,13 +32,18 @@ impl WebSocket {
         } else {
             "ws"
         };
-		let socket_url = format!(
+		let mut socket_url = format!(
             "{}://{}",
             protocol,
 			html.window.location().host().expect("cannot get the url")
 		);
-		html.chat_info("Reconnecting to the server...");
-		let socket = match web_sys::WebSocket::new(&socket_url) {
+		// admission token given to the page (?token=...), forwarded to the server
+		if let Some(token) = html.query("token") {
+			socket_url = format!("{}/?token={}", socket_url, token);
+		}
+		html.fill(ids::SERVER_STATUS_ID, "connecting...");
+		// the server refuse the upgrade if it speaks another version
+		let socket = match web_sys::WebSocket::new_with_str(&socket_url, &subprotocol()) {
 			Ok(socket) => socket,
 			Err(e) => return Err(format!("Error while connecting the server socket: {:?}", e))
 		};
@@ -52,8 +57,10 @@ impl WebSocket {
 				.expect("not a message event")
 				.data();
 			let msg = Uint8Array::new(&msg).to_vec();
-			let msg = WebSocketData::from_u8(msg).expect("Cannot convert server response");
-			sender1.send(Event::ServerMessage(msg));
+			match WebSocketData::from_u8(msg) {
+				Ok(msg) => sender1.send(Event::ServerMessage(msg)),
+				Err(e) => console_log!("Invalid frame from the server: {}", e)
+			}
 		}) as Box<dyn FnMut(JsValue)>);
 		let sender2 = sender.clone();
 		let connected_from_server = Closure::wrap(Box::new(move |_args: JsValue| {
@@ -83,8 +90,16 @@ impl WebSocket {
 		})
 	}
 	
+	// A frame over MAX_FRAME_SIZE cannot be encoded, it is dropped
 	pub fn send(&self, data: &WebSocketData) {
-		if let Err(e) = self.socket.send_with_u8_array(data.into_u8().expect("error while transforming").as_slice()) {
+		let data = match data.into_u8() {
+			Ok(data) => data,
+			Err(e) => {
+				console_log!("Cannot encode a frame for the server: {}", e);
+				return;
+			}
+		};
+		if let Err(e) = self.socket.send_with_u8_array(data.as_slice()) {
 			console_log!("Error while sending to server: {:?}", e)
 		}
 	}
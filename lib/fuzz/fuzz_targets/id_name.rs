@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use crossplatform::id::Id;
+
+// Names typed by the users or sent by the peers, an error but never a panic
+fuzz_target!(|name: &str| {
+	if let Ok(id) = name.parse::<Id>() {
+		assert_eq!(id.to_name().parse(), Ok(id));
+	}
+});
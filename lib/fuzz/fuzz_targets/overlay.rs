@@ -0,0 +1,46 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use crossplatform::id::Id;
+use crossplatform::overlay::{ Overlay, PeerLink, Transport, Ui };
+use crossplatform::proto_rtc::RTCData;
+use crossplatform::topology::Neighbourhood;
+
+// Frames of a neighbour as a client handles them: decoded then processed
+// by the overlay, which forwards them to the other neighbours
+
+struct Link;
+
+impl PeerLink for Link {
+	fn send(&self, data: &[u8]) {
+		RTCData::from_u8(data.to_vec()).expect("the overlay sent an invalid frame");
+	}
+}
+
+struct Connects;
+
+impl Transport for Connects {
+	type Link = Link;
+
+	fn connect(&self, _id: Id) { }
+}
+
+struct Chat;
+
+impl Ui for Chat {
+	fn chat_msg(&self, _from: &str, _msg: &str) { }
+	fn chat_private(&self, _from: &str, _msg: &str) { }
+	fn chat_info(&self, _msg: &str) { }
+	fn neighbours(&self, _neighbours: &[(String, Id)], _cache: &[Id]) { }
+}
+
+fuzz_target!(|data: &[u8]| {
+	let me = Id::new(0, 0);
+	let neighbours = [Id::new(0, 100), Id::new(-100, 0), Id::new(100, 0)];
+	let mut overlay = Overlay::new(me, Neighbourhood::default(), Connects, Chat);
+	for id in neighbours.iter() {
+		overlay.insert(Link, *id);
+	}
+	if let Ok(msg) = RTCData::from_u8(data.to_vec()) {
+		let _ = overlay.process(&msg, neighbours[0]);
+	}
+});
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use crossplatform::proto_rtc::RTCData;
+
+// Frames of the peers, on a data channel or relayed by the server
+fuzz_target!(|data: &[u8]| {
+	if let Ok(msg) = RTCData::from_u8(data.to_vec()) {
+		let encoded = msg.into_u8().expect("a decoded frame cannot be encoded");
+		assert_eq!(RTCData::from_u8(encoded).expect("an encoded frame cannot be decoded"), msg);
+	}
+});
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use crossplatform::proto_ws::WebSocketData;
+
+// Frames of anyone opening a websocket with the server
+fuzz_target!(|data: &[u8]| {
+	if let Ok(msg) = WebSocketData::from_u8(data.to_vec()) {
+		let encoded = msg.into_u8().expect("a decoded frame cannot be encoded");
+		assert_eq!(WebSocketData::from_u8(encoded).expect("an encoded frame cannot be decoded"), msg);
+	}
+});
@@ -0,0 +1,53 @@
+use bincode::Options;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+// The bincode encoding of WebSocketData and RTCData: the format of
+// bincode::serialize (fixed size integers) with frames of at most MAX_FRAME_SIZE.
+// Decoding a slice never reads more than the slice holds, a forged length
+// is an error before anything is allocated
+
+pub const MAX_FRAME_SIZE: u64 = 256 * 1024;
+
+fn options() -> impl Options {
+	bincode::DefaultOptions::new()
+		.with_fixint_encoding()
+		.allow_trailing_bytes()
+		.with_limit(MAX_FRAME_SIZE)
+}
+
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+	if data.len() as u64 > MAX_FRAME_SIZE {
+		return Err(format!("Frame of {} bytes, at most {}", data.len(), MAX_FRAME_SIZE));
+	}
+	options().deserialize(data).map_err(|e| e.to_string())
+}
+
+// Fails rather than sending what the peer would refuse
+pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+	options().serialize(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ decode, encode, MAX_FRAME_SIZE };
+
+	#[test]
+	fn limits() {
+		// same bytes as before the limit
+		let value = (1u32, "hello".to_string(), vec!(1u8, 2));
+		assert_eq!(encode(&value).unwrap(), bincode::serialize(&value).unwrap());
+		assert_eq!(decode::<(u32, String, Vec<u8>)>(&encode(&value).unwrap()), Ok(value));
+
+		// a string of 2^60 bytes
+		let forged = (1u64 << 60).to_le_bytes();
+		assert!(decode::<String>(&forged).is_err());
+		assert!(decode::<Vec<u64>>(&forged).is_err());
+
+		let big = "a".repeat(MAX_FRAME_SIZE as usize + 1);
+		assert!(encode(&big).is_err());
+		assert!(decode::<String>(&bincode::serialize(&big).unwrap()).is_err());
+		let fits = "a".repeat(MAX_FRAME_SIZE as usize - 8);
+		assert_eq!(decode::<String>(&encode(&fits).unwrap()), Ok(fits));
+	}
+}
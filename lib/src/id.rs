@@ -1,6 +1,8 @@
 // use std::convert::TryInto;
 use serde::{Serialize, Deserialize};
-use std::convert::{ TryFrom, TryInto };
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(PartialEq, Debug)]
 pub enum Axe {
@@ -9,13 +11,126 @@ pub enum Axe {
 	Right
 }
 
-const LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIKKLMNOPQRSTUVWXYZ0123456789-_"; 
-const LETTERS_LENGTH: u64 = 64;
-const LENGTHS_BITS: u64 = 6;
+// Names, version 1: the 64 bits in 13 letters of 5 bits, most significant
+// first, then a check letter (Luhn mod 32) catching a mistyped letter and most
+// swapped neighbours. No look-alike letters, decoded case insensitive with o as 0
+// and i, l as 1 (Crockford's base32)
+pub const NAME_VERSION: u32 = 1;
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+const ALPHABET_BITS: u32 = 5;
+const NAME_LETTERS: usize = 13;
+const NAME_LENGTH: usize = NAME_LETTERS + 1; // and the check letter
+
+// Names, version 0: 6 bits letters, least significant first, the leading zeros
+// are dropped (Id(0) has an empty name). The duplicate K makes 36 unreachable,
+// kept as it was so the names out there decode to the same ids
+const LEGACY_LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIKKLMNOPQRSTUVWXYZ0123456789-_";
+const LEGACY_BITS: u64 = 6;
+const LEGACY_MAX_LETTERS: usize = 11; // 64 bits in letters of 6
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Copy, Clone, Hash)]
 pub struct Id(pub u64);
 
+// Why a name is not an Id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+	Empty,
+	InvalidChar(char),
+	Length(usize), // letters, neither a name nor a legacy one
+	Overflow, // the first letter does not fit in the 64 bits
+	Checksum // a mistyped letter
+}
+
+impl fmt::Display for NameError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NameError::Empty => write!(f, "empty name"),
+			NameError::InvalidChar(c) => write!(f, "invalid letter {:?}", c),
+			NameError::Length(len) => write!(f, "{} letters, a name has {}", len, NAME_LENGTH),
+			NameError::Overflow => write!(f, "too big for an id"),
+			NameError::Checksum => write!(f, "wrong check letter, is there a typo?")
+		}
+	}
+}
+
+impl std::error::Error for NameError {}
+
+fn digit(c: char) -> Result<u64, NameError> {
+	let normalized = match c.to_ascii_lowercase() {
+		'o' => '0',
+		'i' | 'l' => '1',
+		c => c
+	};
+	ALPHABET.iter().position(|&letter| letter as char == normalized)
+		.map(|digit| digit as u64)
+		.ok_or(NameError::InvalidChar(c))
+}
+
+// Luhn mod 32 of the digits, most significant first
+fn check_digit(digits: &[u64]) -> u64 {
+	let base = ALPHABET.len() as u64;
+	let sum = digits.iter().rev().enumerate().map(|(i, digit)| {
+		let addend = if i % 2 == 0 { digit * 2 } else { *digit };
+		addend / base + addend % base
+	}).sum::<u64>();
+	(base - sum % base) % base
+}
+
+fn parse_legacy(name: &str) -> Result<Id, NameError> {
+	let mut res: u64 = 0;
+	for (i, c) in name.chars().enumerate() {
+		let index = LEGACY_LETTERS.find(c).ok_or(NameError::InvalidChar(c))? as u64;
+		let decal = i as u64 * LEGACY_BITS;
+		// only 4 bits left for the last letter
+		if decal + LEGACY_BITS > 64 && index >> (64 - decal) != 0 {
+			return Err(NameError::Overflow);
+		}
+		res |= index << decal;
+	}
+	Ok(Id(res))
+}
+
+fn parse(name: &str) -> Result<Id, NameError> {
+	let digits = name.chars().map(digit).collect::<Result<Vec<_>, _>>()?;
+	let (digits, check) = digits.split_at(NAME_LETTERS);
+	// only 4 bits left for the first letter
+	if digits[0] >> (64 - (NAME_LETTERS as u32 - 1) * ALPHABET_BITS) != 0 {
+		return Err(NameError::Overflow);
+	}
+	if check[0] != check_digit(digits) {
+		return Err(NameError::Checksum);
+	}
+	Ok(Id(digits.iter().fold(0, |res, digit| res << ALPHABET_BITS | digit)))
+}
+
+// The version is told by the length
+impl FromStr for Id {
+	type Err = NameError;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		match name.chars().count() {
+			0 => Err(NameError::Empty),
+			1..=LEGACY_MAX_LETTERS => parse_legacy(name),
+			NAME_LENGTH => parse(name),
+			len => Err(NameError::Length(len))
+		}
+	}
+}
+
+impl TryFrom<&str> for Id {
+	type Error = NameError;
+
+	fn try_from(name: &str) -> Result<Self, Self::Error> {
+		name.parse()
+	}
+}
+
+impl fmt::Display for Id {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.to_name())
+	}
+}
+
 impl Id {
 	pub fn new(long: i32, lat: i32) -> Self {
 		Id(long as u32 as u64 + ((lat as u32 as u64) << 32 ))
@@ -26,47 +141,81 @@ impl Id {
 	}
 
 	pub fn to_name(&self) -> String {
-		let mut num = self.0;
-
-		let mut result = String::new();
-		while num != 0 {
-			let character = num & (LETTERS_LENGTH - 1);
-			num = num >> LENGTHS_BITS;
-			assert!(character < LETTERS_LENGTH, "charachter superior of sizeof_letters: {}", character);
-			// unwrap is safe with the assert earlier
-			let character = usize::try_from(character).unwrap();
-			let character = LETTERS.chars().skip(character).next().unwrap();
-			result.push(character);
-		}
-		result
+		let mut digits = (0..NAME_LETTERS)
+			.rev()
+			.map(|i| (self.0 >> (i as u32 * ALPHABET_BITS)) & (ALPHABET.len() as u64 - 1))
+			.collect::<Vec<_>>();
+		digits.push(check_digit(&digits));
+		digits.into_iter().map(|digit| ALPHABET[digit as usize] as char).collect()
 	}
 
-	pub fn from_name(name: &str) -> Self {
-		let mut res: u64 = 0;
-		let mut decal = 0;
-		for c in name.chars() {
-			assert!(decal < 64, "from_name: overflow of u64");
-			let index: u64 = LETTERS.find(c).expect("Invalid letter").try_into().expect("is that a 128 bits computer?"); // TODO
-			res += index << decal;
-			decal += LENGTHS_BITS;
-		}
-		Id(res)
+	// `name.parse::<Id>()` would accept it
+	pub fn is_valid_name(name: &str) -> bool {
+		name.parse::<Id>().is_ok()
 	}
 
 	pub fn get_long(&self) -> i32 {
 		(((self.0) << 32) >> 32) as i32
 	}
 
+	// Manhattan distance, see `Metric` for the others
 	pub fn distance(&self, id2: &Self) -> u64 {
-		println!("lat = {} - {}", self.get_lat(), id2.get_lat());
-		let lat = self.get_lat() as i64 - id2.get_lat() as i64;
-		let long = self.get_long() as i64 - id2.get_long() as i64;
-		(lat.abs() + long.abs()) as u64
+		Metric::Manhattan.distance(*self, *id2)
 	}
 
 	pub fn get_axe(&self, peer: Self) -> Axe {
-		let x = peer.get_long() - self.get_long();
-		let y = peer.get_lat() - self.get_lat();
+		Metric::Manhattan.axe(*self, peer)
+	}
+}
+
+// How far apart two ids are, every node of a network uses the same one (the
+// server announces it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+	#[default]
+	Manhattan, // on the plane, i32::MIN and i32::MAX are the furthest apart
+	Torus // both coordinates wrap around, no id is on the edge
+}
+
+impl FromStr for Metric {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"manhattan" => Ok(Metric::Manhattan),
+			"torus" => Ok(Metric::Torus),
+			_ => Err(format!("unknown metric \"{}\"", s))
+		}
+	}
+}
+
+impl Metric {
+	// Shortest move (long, lat) from `from` to `to`, in i64 so it never overflows
+	fn offset(self, from: Id, to: Id) -> (i64, i64) {
+		match self {
+			Metric::Manhattan => (
+				to.get_long() as i64 - from.get_long() as i64,
+				to.get_lat() as i64 - from.get_lat() as i64
+			),
+			// at exactly half the way around, both ways are negative
+			Metric::Torus => (
+				to.get_long().wrapping_sub(from.get_long()) as i64,
+				to.get_lat().wrapping_sub(from.get_lat()) as i64
+			)
+		}
+	}
+
+	pub fn distance(self, a: Id, b: Id) -> u64 {
+		#[cfg(feature = "tracing")]
+		tracing::trace!(lat1 = a.get_lat(), lat2 = b.get_lat(), "distance");
+		let (long, lat) = self.offset(a, b);
+		(long.abs() + lat.abs()) as u64
+	}
+
+	// The sector of `peer` seen from `from`
+	pub fn axe(self, from: Id, peer: Id) -> Axe {
+		let (x, y) = self.offset(from, peer);
 		if x == 0 && y >= 0 {
 			Axe::Top
 		} else if x == 0 {
@@ -85,12 +234,45 @@ impl Id {
 			Axe::Left
 		}
 	}
+
 }
 
 
 #[cfg(test)]
 mod tests {
-	use super::{ Id, Axe };
+	use std::convert::TryFrom;
+	use proptest::prelude::*;
+	use super::{ Id, Axe, Metric, NameError };
+
+	fn metric() -> impl Strategy<Value = Metric> {
+		prop_oneof![Just(Metric::Manhattan), Just(Metric::Torus)]
+	}
+
+	fn id() -> impl Strategy<Value = Id> {
+		any::<u64>().prop_map(Id)
+	}
+
+	proptest! {
+		#[test]
+		fn symmetry(metric in metric(), a in id(), b in id()) {
+			prop_assert_eq!(metric.distance(a, b), metric.distance(b, a));
+			prop_assert_eq!(metric.distance(a, a), 0);
+			// never overflows
+			metric.axe(a, b);
+		}
+
+		#[test]
+		fn triangle_inequality(metric in metric(), a in id(), b in id(), c in id()) {
+			prop_assert!(metric.distance(a, c) <= metric.distance(a, b) + metric.distance(b, c));
+		}
+
+		#[test]
+		fn name_round_trip(id in any::<u64>()) {
+			let name = Id(id).to_name();
+			prop_assert_eq!(name.chars().count(), super::NAME_LENGTH);
+			prop_assert_eq!(name.parse(), Ok(Id(id)));
+		}
+	}
 
 	#[test]
 	fn id_test() {
@@ -110,6 +292,64 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn name_test() {
+		let id = Id::new(-500, 99);
+		assert!(Id::is_valid_name(&id.to_name()));
+		assert_eq!(id.to_name().parse(), Ok(id));
+		assert!(Id::is_valid_name("abc-_09"));
+		assert!(Id::is_valid_name("aaaaaaaaaaa"));
+		assert!(!Id::is_valid_name("aaaaaaaaaaaa"));
+		assert!(Id::is_valid_name(&Id(u64::MAX).to_name()));
+		assert!(!Id::is_valid_name("aaaaaaaaaaq")); // 16 << 60
+		assert!(!Id::is_valid_name(""));
+		assert!(!Id::is_valid_name("a b"));
+		assert!(!Id::is_valid_name("é"));
+	}
+
+	#[test]
+	fn name_errors() {
+		assert_eq!("".parse::<Id>(), Err(NameError::Empty));
+		assert_eq!("ab cd".parse::<Id>(), Err(NameError::InvalidChar(' ')));
+		assert_eq!("abcé".parse::<Id>(), Err(NameError::InvalidChar('é')));
+		assert_eq!("aaaaaaaaaaaa".parse::<Id>(), Err(NameError::Length(12)));
+		assert_eq!("aaaaaaaaaaq".parse::<Id>(), Err(NameError::Overflow));
+		assert_eq!("__________p".parse::<Id>(), Ok(Id(u64::MAX)));
+		assert_eq!(Id::try_from("b"), Ok(Id(1)));
+		let id = Id::new(-500, 99);
+		assert_eq!(id.to_string(), id.to_name());
+		assert_eq!(NameError::Length(12).to_string(), "12 letters, a name has 14");
+	}
+
+	#[test]
+	fn names() {
+		assert_eq!(Id(0).to_name(), "00000000000000");
+		assert_eq!(Id(u64::MAX).to_name().len(), 14);
+		let id = Id::new(-500, 99);
+		let name = id.to_name();
+		assert_eq!(name.len(), 14);
+		assert_eq!(name.to_uppercase().parse(), Ok(id));
+		assert_eq!(name.replace('0', "o").replace('1', "l").parse(), Ok(id));
+
+		// every typo is caught
+		for i in 0..name.len() {
+			for letter in super::ALPHABET.iter().map(|&letter| letter as char) {
+				let mut typo = name.clone();
+				typo.replace_range(i..i + 1, &letter.to_string());
+				if typo != name {
+					assert!(typo.parse::<Id>().is_err(), "{} accepted for {}", typo, name);
+				}
+			}
+		}
+		assert_eq!("g0000000000000".parse::<Id>(), Err(NameError::Overflow));
+		assert_eq!("u0000000000000".parse::<Id>(), Err(NameError::InvalidChar('u')));
+
+		// legacy names, 36 is unreachable as before
+		assert_eq!("cyV62vKEhCf".parse(), Ok(Id(6271115376183670274)));
+		assert_eq!("m4___pg".parse(), Ok(id));
+		assert_eq!("Kf".parse(), Ok(Id(355)));
+	}
+
 	#[test]
 	fn distance_test()
 	{
@@ -127,6 +367,21 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn torus() {
+		let (west, east) = (Id::new(i32::MIN, 0), Id::new(i32::MAX, 0));
+		assert_eq!(Metric::Manhattan.distance(west, east), u32::MAX as u64);
+		assert_eq!(Metric::Torus.distance(west, east), 1);
+		assert_eq!(Metric::Torus.axe(east, west), Axe::Right);
+		assert_eq!(Metric::Torus.axe(west, east), Axe::Left);
+		assert_eq!(Metric::Torus.distance(Id::new(0, i32::MIN), Id::new(0, 0)), 1 << 31);
+		assert_eq!(Metric::Torus.distance(Id::new(5, 0), Id::new(0, 12)), 17);
+		// the largest offsets, once an overflow
+		assert_eq!(Metric::Manhattan.axe(Id::new(i32::MIN, 0), Id::new(i32::MAX, 1)), Axe::Right);
+		assert_eq!("Torus".parse(), Ok(Metric::Torus));
+		assert!("euclid".parse::<Metric>().is_err());
+	}
+
 	#[test]
 	fn axe_test() {
 		let coords = vec![
@@ -161,7 +416,7 @@ mod tests {
 		];
 
 		for id in ids {
-			assert_eq!(id.0, Id::from_name(id.to_name().as_str()).0);
+			assert_eq!(Id::try_from(id.to_name().as_str()), Ok(id));
  		}
 	}
 }
\ No newline at end of file
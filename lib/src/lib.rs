@@ -1,3 +1,6 @@
 pub mod proto_ws;
 pub mod proto_rtc;
-pub mod id;
\ No newline at end of file
+pub mod id;
+pub mod overlay;
+pub mod topology;
+pub mod codec;
\ No newline at end of file
@@ -0,0 +1,588 @@
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use crate::id::Id;
+use crate::proto_rtc::{ RTCData, RTCContent };
+use crate::topology::{ Topology, Neighbourhood };
+
+// The overlay without the platform: a node keeps the closest peers of each
+// slot of its topology (by default the top, left and right ones) and the
+// others in a cache, floods the broadcasts and routes the messages with a
+// target greedily
+
+pub const GOSSIP_INTERVAL: u64 = 30_000; // ms between two gossips of a node
+const GOSSIP_SAMPLE: usize = 8; // ids of the cache shared with each neighbour
+const CACHE_MAX: usize = 16; // peers kept besides the neighbours, the oldest links are closed
+const SEEN_MAX: usize = 1024; // broadcasts remembered to drop their copies, the oldest are forgotten
+
+// An open connection with a peer
+pub trait PeerLink {
+	fn send(&self, data: &[u8]);
+
+	// Address of the peer when the server relays the link
+	fn relayed(&self) -> Option<SocketAddr> {
+		None
+	}
+
+	// The overlay drops the link, the peer should know
+	fn close(&self) { }
+}
+
+// Opens the links
+pub trait Transport {
+	type Link: PeerLink;
+
+	// Ask for a link with `id`, given back with `Overlay::insert` once open
+	fn connect(&self, id: Id);
+
+	// A link with `id` is being opened
+	fn is_pending(&self, _id: Id) -> bool {
+		false
+	}
+}
+
+// What the user sees
+pub trait Ui {
+	fn chat_msg(&self, from: &str, msg: &str);
+	fn chat_private(&self, from: &str, msg: &str);
+	fn chat_info(&self, msg: &str);
+	fn neighbours(&self, neighbours: &[(String, Id)], cache: &[Id]); // with the label of their slot
+}
+
+#[derive(Debug)]
+struct Peer<L> {
+	id: Id,
+	link: L
+}
+
+impl<L: PeerLink> Peer<L> {
+	fn send(&self, from: Id, data_from: Id, data: &[u8]) {
+		if self.id != from && self.id != data_from {
+			self.link.send(data);
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Overlay<T: Transport, U: Ui, N: Topology = Neighbourhood> {
+	pub id: Id,
+	topology: N,
+	neighbours: Vec<Peer<T::Link>>, // by slot then distance
+	peer_cache: Vec<Peer<T::Link>>,
+	round: usize, // gossip rounds, to share the whole cache over time
+	seen: Mutex<VecDeque<(Id, u32)>>, // `from` and `id` of the last broadcasts
+	next_id: AtomicU32, // of the data we send
+	transport: T,
+	ui: U
+}
+
+impl<T: Transport, U: Ui, N: Topology> Overlay<T, U, N> {
+	pub fn new(id: Id, topology: N, transport: T, ui: U) -> Self {
+		Overlay {
+			id,
+			topology,
+			neighbours: vec!(),
+			peer_cache: vec!(),
+			round: 0,
+			seen: Mutex::new(VecDeque::new()),
+			next_id: AtomicU32::new(0),
+			transport,
+			ui
+		}
+	}
+
+	pub fn transport(&self) -> &T {
+		&self.transport
+	}
+
+	pub fn transport_mut(&mut self) -> &mut T {
+		&mut self.transport
+	}
+
+	pub fn ui(&self) -> &U {
+		&self.ui
+	}
+
+	pub fn topology(&self) -> &N {
+		&self.topology
+	}
+
+	// The network changed its topology, the peers take new slots
+	pub fn set_topology(&mut self, topology: N) {
+		self.topology = topology;
+		let mut peers = std::mem::take(&mut self.neighbours);
+		peers.append(&mut self.peer_cache);
+		peers.into_iter().for_each(|peer| self.place(peer));
+		self.refresh();
+	}
+
+	fn distance(&self, id: Id) -> u64 {
+		self.topology.distance(self.id, id)
+	}
+
+	fn slot(&self, id: Id) -> usize {
+		self.topology.slot(self.id, id)
+	}
+
+	fn peers(&self) -> impl Iterator<Item = &Peer<T::Link>> {
+		self.neighbours.iter().chain(self.peer_cache.iter())
+	}
+
+	// Among the closest of its slot the peer is a neighbour, the furthest one
+	// goes in the cache
+	fn place(&mut self, peer: Peer<T::Link>) {
+		let slot = self.slot(peer.id);
+		let distance = self.distance(peer.id);
+		let same_slot = self.neighbours.iter()
+			.enumerate()
+			.filter(|(_, other)| self.slot(other.id) == slot)
+			.map(|(index, other)| (index, self.distance(other.id)))
+			.collect::<Vec<_>>();
+		if same_slot.len() >= self.topology.capacity() {
+			match same_slot.iter().max_by_key(|(_, other)| *other) {
+				Some((index, furthest)) if distance < *furthest => {
+					let old = self.neighbours.remove(*index);
+					self.cache(old);
+				},
+				_ => {
+					self.cache(peer);
+					return;
+				}
+			}
+		}
+		let position = self.neighbours.iter()
+			.position(|other| (self.slot(other.id), self.distance(other.id)) > (slot, distance))
+			.unwrap_or(self.neighbours.len());
+		self.neighbours.insert(position, peer);
+	}
+
+	fn cache(&mut self, peer: Peer<T::Link>) {
+		self.peer_cache.push(peer);
+		if self.peer_cache.len() > CACHE_MAX {
+			self.peer_cache.remove(0).link.close();
+		}
+	}
+
+	pub fn insert(&mut self, link: T::Link, id: Id) {
+		self.place(Peer { id, link });
+		self.refresh();
+	}
+
+	pub fn len(&self) -> usize {
+		self.peers().count()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	// Connected or being connected
+	pub fn contains(&self, id: Id) -> bool {
+		id == self.id || self.transport.is_pending(id) || self.peers().any(|peer| peer.id == id)
+	}
+
+	// Id of the peer relayed by the server from this addr
+	pub fn relayed(&self, addr: SocketAddr) -> Option<Id> {
+		self.peers()
+			.find(|peer| peer.link.relayed() == Some(addr))
+			.map(|peer| peer.id)
+	}
+
+	pub fn remove(&mut self, id: Id) -> Result<(), String> {
+		// TODO: Replace one of the side from a peer from the cache
+		if let Some(index) = self.neighbours.iter().position(|x| x.id == id) {
+			self.neighbours.remove(index);
+		} else if let Some(index) = self.peer_cache.iter().position(|x| x.id == id) {
+			self.peer_cache.remove(index);
+		} else {
+			return Err("Unknow Peer as disconnected".to_string());
+		}
+		self.ui.chat_info(format!("{} as disconnected.", id.to_name()).as_str());
+		self.refresh();
+		Ok(())
+	}
+
+	// Drop every link matching `drop`, like the ones relayed by a server that is gone
+	pub fn remove_links(&mut self, drop: impl Fn(&T::Link) -> bool) {
+		self.neighbours.retain(|peer| !drop(&peer.link));
+		self.peer_cache.retain(|peer| !drop(&peer.link));
+		self.refresh();
+	}
+
+	pub fn process(&self, data: &RTCData, from: Id) -> Result<(), String> {
+		match &data.content {
+			RTCContent::Message(msg) => {
+				match data.to {
+					Some(target) if target == self.id => self.ui.chat_private(data.from.to_name().as_str(), msg.as_str()),
+					Some(_) => self.send(data, from)?,
+					None => {
+						if !self.seen(data) {
+							self.ui.chat_msg(data.from.to_name().as_str(), msg.as_str());
+							self.send(data, from)?;
+						}
+					}
+				}
+			},
+			RTCContent::Received(_id, _timestamp) => { }
+			RTCContent::NotFound => { },
+			RTCContent::Hello => { },
+			// only from the neighbour itself, connect to the closer ones
+			RTCContent::Neighbours(ids) => {
+				if data.from == from {
+					self.candidates(ids).into_iter().for_each(|id| self.transport.connect(id));
+				}
+			},
+			// the ones for us are handled by the transport
+			RTCContent::Offer(_) | RTCContent::Answer(_) | RTCContent::IceCandidate(_) => {
+				if data.to != Some(self.id) {
+					self.send(data, from)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// The broadcasts loop in the cycles of the overlay, only the first copy is
+	// handled. Ours are always seen
+	fn seen(&self, data: &RTCData) -> bool {
+		if data.from == self.id {
+			return true;
+		}
+		let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+		if seen.contains(&(data.from, data.id)) {
+			return true;
+		}
+		if seen.len() >= SEEN_MAX {
+			seen.pop_front();
+		}
+		seen.push_back((data.from, data.id));
+		false
+	}
+
+	fn next_id(&self) -> u32 {
+		self.next_id.fetch_add(1, Ordering::Relaxed)
+	}
+
+	// Greedy routing: the neighbour closest to `to`, if it gets the data closer than us
+	fn next_hop(&self, to: Id, from: Id, data_from: Id) -> Option<&Peer<T::Link>> {
+		let best = self.peers()
+			.filter(|peer| peer.id != from && peer.id != data_from)
+			.min_by_key(|peer| self.topology.distance(peer.id, to))?;
+		if best.id == to || self.topology.distance(best.id, to) < self.distance(to) {
+			Some(best)
+		} else {
+			None
+		}
+	}
+
+	// Flood the broadcasts, route the others, never back to `from`
+	pub fn send(&self, data: &RTCData, from: Id) -> Result<(), String> {
+		let bytes = data.into_u8()?;
+		match data.to {
+			Some(to) => {
+				let peer = self.next_hop(to, from, data.from).ok_or(format!("No route to {}", to.to_name()))?;
+				peer.link.send(bytes.as_slice());
+			},
+			None => self.peers().for_each(|peer| peer.send(from, data.from, bytes.as_slice()))
+		}
+		Ok(())
+	}
+
+	// Route `content` up to the peer `to`
+	pub fn send_to(&self, content: RTCContent, to: Id) -> Result<(), String> {
+		let data = RTCData {
+			to: Some(to),
+			id: self.next_id(),
+			timestamp: 0,
+			from: self.id,
+			content
+		};
+		self.send(&data, self.id)
+	}
+
+	// Flood `content` to the whole network
+	pub fn broadcast(&self, content: RTCContent) -> Result<(), String> {
+		let data = RTCData {
+			to: None,
+			id: self.next_id(),
+			timestamp: 0,
+			from: self.id,
+			content
+		};
+		self.send(&data, self.id)
+	}
+
+	// Share our neighbours and a slice of the cache with every peer
+	pub fn gossip(&mut self) -> Result<(), String> {
+		let cache = self.peer_cache.iter().map(|peer| peer.id).collect::<Vec<_>>();
+		let start = if cache.is_empty() { 0 } else { self.round * GOSSIP_SAMPLE % cache.len() };
+		self.round = self.round.wrapping_add(1);
+		let ids = self.neighbours.iter()
+			.map(|peer| peer.id)
+			.chain(cache.iter().cycle().skip(start).take(GOSSIP_SAMPLE.min(cache.len())).copied())
+			.collect::<Vec<_>>();
+		for peer in self.peers() {
+			let data = RTCData {
+				to: Some(peer.id),
+				id: self.next_id(),
+				timestamp: 0,
+				from: self.id,
+				content: RTCContent::Neighbours(ids.iter().copied().filter(|id| *id != peer.id).collect())
+			};
+			peer.link.send(data.into_u8()?.as_slice());
+		}
+		Ok(())
+	}
+
+	// The gossiped ids that would be among the closest of their slot, counting
+	// our neighbours and the other ids
+	pub fn candidates(&self, ids: &[Id]) -> Vec<Id> {
+		let mut unique: Vec<Id> = vec!();
+		for id in ids.iter().copied().filter(|id| !self.contains(*id)) {
+			if !unique.contains(&id) {
+				unique.push(id);
+			}
+		}
+		unique.iter().copied().filter(|id| {
+			let (slot, distance) = (self.slot(*id), self.distance(*id));
+			let closer_neighbours = self.neighbours.iter()
+				.filter(|peer| self.slot(peer.id) == slot && self.distance(peer.id) <= distance)
+				.count();
+			let closer_ids = unique.iter()
+				.filter(|other| self.slot(**other) == slot && self.distance(**other) < distance)
+				.count();
+			closer_neighbours + closer_ids < self.topology.capacity()
+		}).collect()
+	}
+
+	pub fn refresh(&self) {
+		let cache = self.peer_cache.iter().map(|peer| peer.id).collect::<Vec<_>>();
+		let neighbours = self.neighbours.iter()
+			.map(|peer| (self.topology.label(self.slot(peer.id)), peer.id))
+			.collect::<Vec<_>>();
+		self.ui.neighbours(&neighbours, &cache);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use crate::id::{ Id, Metric };
+	use crate::proto_rtc::{ RTCData, RTCContent };
+	use crate::topology::{ Neighbourhood, Slots };
+	use super::{ Overlay, Peer, PeerLink, Transport, Ui, CACHE_MAX };
+
+	// Every frame sent, by link
+	type Sent = Rc<RefCell<Vec<(Id, RTCData)>>>;
+
+	// The links closed by the overlay
+	type Closed = Rc<RefCell<Vec<Id>>>;
+
+	struct Link(Id, Sent, Closed);
+
+	impl PeerLink for Link {
+		fn send(&self, data: &[u8]) {
+			self.1.borrow_mut().push((self.0, RTCData::from_u8(data.to_vec()).unwrap()));
+		}
+
+		fn close(&self) {
+			self.2.borrow_mut().push(self.0);
+		}
+	}
+
+	#[derive(Default)]
+	struct Connects(RefCell<Vec<Id>>);
+
+	impl Transport for Connects {
+		type Link = Link;
+
+		fn connect(&self, id: Id) {
+			self.0.borrow_mut().push(id);
+		}
+	}
+
+	#[derive(Default)]
+	struct Chat(RefCell<Vec<String>>);
+
+	impl Ui for Chat {
+		fn chat_msg(&self, from: &str, msg: &str) {
+			self.0.borrow_mut().push(format!("{}: {}", from, msg));
+		}
+
+		fn chat_private(&self, from: &str, msg: &str) {
+			self.0.borrow_mut().push(format!("{} (private): {}", from, msg));
+		}
+
+		fn chat_info(&self, _msg: &str) { }
+
+		fn neighbours(&self, _neighbours: &[(String, Id)], _cache: &[Id]) { }
+	}
+
+	fn overlay_with(me: Id, topology: Neighbourhood, peers: &[Id]) -> (Overlay<Connects, Chat>, Sent) {
+		let sent = Sent::default();
+		let mut overlay = Overlay::new(me, topology, Connects::default(), Chat::default());
+		for id in peers {
+			overlay.insert(Link(*id, sent.clone(), Closed::default()), *id);
+		}
+		(overlay, sent)
+	}
+
+	fn overlay(peers: &[Id]) -> (Overlay<Connects, Chat>, Sent) {
+		overlay_with(Id::new(0, 0), Neighbourhood::default(), peers)
+	}
+
+	fn ids(peers: &[Peer<Link>]) -> Vec<Id> {
+		peers.iter().map(|peer| peer.id).collect()
+	}
+
+	fn message(from: Id, to: Option<Id>) -> RTCData {
+		RTCData { to, id: 0, timestamp: 0, from, content: RTCContent::Message("hi".to_string()) }
+	}
+
+	fn targets(sent: &Sent) -> Vec<Id> {
+		sent.borrow_mut().drain(..).map(|(link, _)| link).collect()
+	}
+
+	#[test]
+	fn slots() {
+		let (top, far_top, right, left) = (Id::new(0, 2), Id::new(0, 5), Id::new(3, 0), Id::new(-3, 0));
+		let (mut overlay, _) = overlay(&[far_top, right, top, left]);
+		assert_eq!(ids(&overlay.neighbours), vec!(top, left, right));
+		assert_eq!(ids(&overlay.peer_cache), vec!(far_top));
+		assert_eq!(overlay.len(), 4);
+		assert!(overlay.contains(far_top) && overlay.contains(overlay.id));
+
+		overlay.remove(right).unwrap();
+		overlay.remove(far_top).unwrap();
+		assert!(ids(&overlay.neighbours) == vec!(top, left) && overlay.peer_cache.is_empty());
+		assert!(overlay.remove(right).is_err());
+		overlay.remove_links(|link| link.0 == top);
+		assert_eq!(overlay.len(), 1);
+	}
+
+	#[test]
+	fn flooding() {
+		let (a, b, c) = (Id::new(0, 2), Id::new(3, 0), Id::new(-3, 0));
+		let (overlay, sent) = overlay(&[a, b, c]);
+		overlay.process(&message(a, None), a).unwrap();
+		assert_eq!(overlay.ui.0.borrow().len(), 1);
+		// never back to the sender
+		assert_eq!(targets(&sent), vec!(c, b));
+		// nor to the author
+		overlay.process(&message(c, None), b).unwrap();
+		assert_eq!(targets(&sent), vec!(a));
+
+		// the copies coming back through a cycle are dropped, ours too
+		overlay.process(&message(a, None), b).unwrap();
+		overlay.process(&message(overlay.id, None), c).unwrap();
+		assert!(targets(&sent).is_empty());
+		assert_eq!(overlay.ui.0.borrow().len(), 2);
+		overlay.broadcast(RTCContent::Message("hi".to_string())).unwrap();
+		overlay.broadcast(RTCContent::Message("hi".to_string())).unwrap();
+		let sent = sent.borrow();
+		assert_eq!(sent.len(), 6);
+		assert!(sent[0].1.id != sent[3].1.id);
+	}
+
+	#[test]
+	fn routing() {
+		let (near, far) = (Id::new(0, 2), Id::new(-9, -9));
+		let (overlay, sent) = overlay(&[near, far]);
+		let target = Id::new(0, 10);
+		overlay.process(&message(far, Some(target)), far).unwrap();
+		assert_eq!(targets(&sent), vec!(near));
+		// nobody closer than us
+		assert!(overlay.send_to(RTCContent::Hello, Id::new(9, -9)).is_err());
+		assert!(overlay.send_to(RTCContent::Hello, far).is_ok());
+		assert_eq!(targets(&sent), vec!(far));
+
+		overlay.process(&message(near, Some(overlay.id)), near).unwrap();
+		assert_eq!(overlay.ui.0.borrow().last().unwrap(), &format!("{} (private): hi", near.to_name()));
+		assert!(targets(&sent).is_empty());
+	}
+
+	#[test]
+	fn gossip() {
+		let (top, cached, right) = (Id::new(0, 2), Id::new(0, 4), Id::new(5, 0));
+		let (mut overlay, sent) = overlay(&[top, cached, right]);
+		overlay.gossip().unwrap();
+		let sent = sent.borrow();
+		assert_eq!(sent.len(), 3);
+		let (link, data) = &sent[0];
+		assert_eq!(data.to, Some(*link));
+		match &data.content {
+			RTCContent::Neighbours(ids) => assert_eq!(ids, &vec!(right, cached)),
+			content => panic!("not neighbours: {:?}", content)
+		}
+
+		// closer on the right, further on top, and a free left side
+		let (closer, further, left) = (Id::new(2, 0), Id::new(0, 3), Id::new(-7, 0));
+		assert_eq!(overlay.candidates(&[further, closer, left, top, Id::new(-9, 0)]), vec!(closer, left));
+		let data = RTCData { to: Some(overlay.id), id: 0, timestamp: 0, from: top, content: RTCContent::Neighbours(vec!(closer)) };
+		overlay.process(&data, right).unwrap();
+		assert!(overlay.transport.0.borrow().is_empty());
+		overlay.process(&data, top).unwrap();
+		assert_eq!(*overlay.transport.0.borrow(), vec!(closer));
+	}
+
+	#[test]
+	fn metric() {
+		// far apart on the plane, next to us once it wraps around
+		let (me, top, wrapped) = (Id::new(i32::MAX - 10, 0), Id::new(i32::MAX - 10, 10), Id::new(i32::MIN + 10, 0));
+		let (mut overlay, _) = overlay_with(me, Neighbourhood::default(), &[top, wrapped]);
+		assert_eq!(ids(&overlay.neighbours), vec!(top, wrapped)); // on the left
+		overlay.set_topology(Neighbourhood { metric: Metric::Torus, ..Neighbourhood::default() });
+		assert_eq!(ids(&overlay.neighbours), vec!(top, wrapped)); // on the right
+		assert_eq!(overlay.slot(wrapped), 2);
+		assert!(overlay.peer_cache.is_empty());
+		let (closer, left) = (Id::new(i32::MIN + 5, 0), Id::new(0, 0));
+		assert_eq!(overlay.candidates(&[closer, left]), vec!(closer, left));
+	}
+
+	#[test]
+	fn nearest() {
+		let nearest = Neighbourhood { slots: Slots::Nearest, k: 2, ..Neighbourhood::default() };
+		let (a, b, c) = (Id::new(0, 3), Id::new(-1, 0), Id::new(2, 0));
+		let (mut overlay, _) = overlay_with(Id::new(0, 0), nearest, &[a, b, c]);
+		assert_eq!(ids(&overlay.neighbours), vec!(b, c));
+		assert_eq!(ids(&overlay.peer_cache), vec!(a));
+		// the closest of the gossip take the places of the furthest
+		let (d, e) = (Id::new(1, 0), Id::new(0, -1));
+		assert_eq!(overlay.candidates(&[Id::new(9, 9), d, e, d]), vec!(d, e));
+		assert_eq!(overlay.candidates(&[Id::new(0, 2), Id::new(3, 3)]), vec!());
+		overlay.insert(Link(d, Rc::default(), Rc::default()), d);
+		assert_eq!(ids(&overlay.neighbours), vec!(b, d));
+		assert_eq!(ids(&overlay.peer_cache), vec!(a, c));
+	}
+
+	#[test]
+	fn buckets() {
+		let buckets = Neighbourhood { slots: Slots::Buckets, k: 1, ..Neighbourhood::default() };
+		let (mut overlay, sent) = overlay_with(Id(0b1000), buckets, &[Id(0b1001), Id(0b1110), Id(0b1100), Id(0)]);
+		assert_eq!(ids(&overlay.neighbours), vec!(Id(0b1001), Id(0b1100), Id(0)));
+		assert_eq!(ids(&overlay.peer_cache), vec!(Id(0b1110)));
+		// to the peer sharing the most leading bits with the target
+		overlay.send_to(RTCContent::Hello, Id(0b1111)).unwrap();
+		assert_eq!(targets(&sent), vec!(Id(0b1110)));
+		overlay.send_to(RTCContent::Hello, Id(0b0011)).unwrap();
+		assert_eq!(targets(&sent), vec!(Id(0)));
+		overlay.remove(Id(0b1100)).unwrap();
+		assert_eq!(overlay.candidates(&[Id(0b1101), Id(0b0001)]), vec!(Id(0b1101)));
+	}
+
+	#[test]
+	fn bounded_cache() {
+		let nearest = Neighbourhood { slots: Slots::Nearest, k: 1, ..Neighbourhood::default() };
+		let (mut overlay, _) = overlay_with(Id::new(0, 0), nearest, &[Id::new(0, 1)]);
+		let closed = Closed::default();
+		for y in 2..CACHE_MAX as i32 + 4 {
+			overlay.insert(Link(Id::new(0, y), Sent::default(), closed.clone()), Id::new(0, y));
+		}
+		assert_eq!(overlay.peer_cache.len(), CACHE_MAX);
+		// the oldest ones are closed
+		assert_eq!(*closed.borrow(), vec!(Id::new(0, 2), Id::new(0, 3)));
+		assert!(!overlay.contains(Id::new(0, 2)) && overlay.contains(Id::new(0, 4)));
+	}
+}
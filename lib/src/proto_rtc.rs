@@ -1,8 +1,10 @@
 use serde::{Serialize, Deserialize};
 use crate::id::Id;
+use crate::codec;
+use crate::proto_ws::IceCandidateStruct;
 
 // Present here for the serde crate
-#[derive(Serialize, Deserialize, Debug, Hash)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Hash)]
 pub struct RTCData {
 	pub id: u32, // Random generated id
 	pub timestamp: u32,
@@ -11,19 +13,77 @@ pub struct RTCData {
 	pub to: Option<Id> // target or broadcast
 }
 
-#[derive(Serialize, Deserialize, Debug, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Hash)]
 pub enum RTCContent {
 	Message(String), // Private or Broadcast
 	Received(u32, u32), // id and timestamp
 	NotFound, // Nearest peer doesnt know
+	Hello, // First frame on a link relayed by the server, `from` is the sender id
+	// Peer assisted signaling, routed through the overlay from `from` to `to`
+	Offer(String), // SDP
+	Answer(String), // SDP
+	IceCandidate(IceCandidateStruct),
+	Neighbours(Vec<Id>), // gossip: top, left, right and a sample of the cache of `from`
 }
 
 impl RTCData {
 	pub fn from_u8(data: Vec<u8>) -> Result<Self, String> {
-		bincode::deserialize(&data[..]).map_err(|e| e.to_string())
+		codec::decode(&data)
 	}
 
 	pub fn into_u8(&self) -> Result<Vec<u8>, String> {
-		bincode::serialize(self).map_err(|e| e.to_string())
+		codec::encode(self)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub mod tests {
+	use proptest::prelude::*;
+	use proptest::collection::vec;
+	use proptest::option;
+	use crate::id::Id;
+	use crate::proto_ws::IceCandidateStruct;
+	use super::{ RTCData, RTCContent };
+
+	pub fn id() -> impl Strategy<Value = Id> {
+		any::<u64>().prop_map(Id)
+	}
+
+	pub fn candidate() -> impl Strategy<Value = IceCandidateStruct> {
+		(any::<String>(), option::of(any::<String>()), option::of(any::<u16>()))
+			.prop_map(|(candidate, sdp_mid, sdp_m_line_index)| IceCandidateStruct { candidate, sdp_mid, sdp_m_line_index })
+	}
+
+	fn content() -> impl Strategy<Value = RTCContent> {
+		prop_oneof![
+			any::<String>().prop_map(RTCContent::Message),
+			(any::<u32>(), any::<u32>()).prop_map(|(id, timestamp)| RTCContent::Received(id, timestamp)),
+			Just(RTCContent::NotFound),
+			Just(RTCContent::Hello),
+			any::<String>().prop_map(RTCContent::Offer),
+			any::<String>().prop_map(RTCContent::Answer),
+			candidate().prop_map(RTCContent::IceCandidate),
+			vec(id(), 0..16).prop_map(RTCContent::Neighbours)
+		]
+	}
+
+	fn data() -> impl Strategy<Value = RTCData> {
+		(any::<u32>(), any::<u32>(), id(), content(), option::of(id()))
+			.prop_map(|(id, timestamp, from, content, to)| RTCData { id, timestamp, from, content, to })
+	}
+
+	proptest! {
+		#[test]
+		fn round_trip(data in data()) {
+			prop_assert_eq!(RTCData::from_u8(data.into_u8().unwrap()).unwrap(), data);
+		}
+
+		// an error, never a panic
+		#[test]
+		fn garbage(bytes in vec(any::<u8>(), 0..256)) {
+			if let Ok(data) = RTCData::from_u8(bytes) {
+				prop_assert_eq!(RTCData::from_u8(data.into_u8().unwrap()).unwrap(), data);
+			}
+		}
+	}
+}
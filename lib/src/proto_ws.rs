@@ -1,34 +1,141 @@
 use serde::{Serialize, Deserialize};
 use std::net::SocketAddr;
 use crate::id::Id;
+use crate::topology::Neighbourhood;
+use crate::codec;
 // Structures that will be send across the websocket
 // in a client-server connection
 
+// Bumped on every incompatible change of WebSocketData, negotiated
+// as the websocket subprotocol
+pub const PROTOCOL_VERSION: u32 = 6;
+pub const SUBPROTOCOL_PREFIX: &str = "p2p-chat.v";
+pub const GOING_AWAY_MAX: u64 = 600_000; // ms, a client never waits longer whatever GoingAway says
 
-#[derive(Serialize, Deserialize, Debug)]
+pub fn subprotocol() -> String {
+	format!("{}{}", SUBPROTOCOL_PREFIX, PROTOCOL_VERSION)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Hash)]
 pub struct IceCandidateStruct {
 	pub candidate: String,
 	pub sdp_mid: Option<String>,
 	pub sdp_m_line_index: Option<u16>
 }
 
+// Same fields as the javascript RTCIceServer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IceServer {
+	pub urls: Vec<String>, // stun: or turn: urls
+	pub username: Option<String>,
+	pub credential: Option<String>
+}
+
+impl IceServer {
+	pub fn stun(url: &str) -> Self {
+		IceServer { urls: vec!(url.to_string()), username: None, credential: None }
+	}
+}
+
+// Time limited TURN access (TURN REST api), the client ask for new ones before they expire
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TurnCredentials {
+	pub server: IceServer,
+	pub ttl: u64 // validity in seconds from the emission
+}
+
 // Make it an enum ? (no method field)
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum WebSocketData {
 	OfferSDP(String, Option<SocketAddr>),
 	AnswerSDP(String, SocketAddr),
 	IceCandidate(IceCandidateStruct, SocketAddr),
 	Message(String), // For testing purpose
 	Id(Option<Id>),
+	IceServers(Vec<IceServer>), // server -> client after connect, to create the RtcPeerConnection
+	TurnCredentials(Option<TurnCredentials>), // None to ask for fresh ones
+	Relay(Vec<u8>, SocketAddr), // RTCData forwarded by the server when WebRTC cannot connect
+	RelayClosed(SocketAddr), // relay disabled, quota reached or peer gone
+	Notice(String), // server -> client, announcement from the operators
+	GoingAway(u64), // server -> client, shutting down: reconnect at a random time within this many seconds
+	Resume(Option<String>), // client -> server after connect, with the last session token if any
+	Session(Id, String), // server -> client, the Id (the old one if resumed) and a new session token
+	Neighbourhood(Neighbourhood), // server -> client after connect, how the neighbours are chosen
 	// TODO: whoami
 }
 
 impl WebSocketData {
 	pub fn from_u8(data: Vec<u8>) -> Result<Self, String> {
-		bincode::deserialize(&data[..]).map_err(|e| e.to_string())
+		codec::decode(&data)
 	}
 
 	pub fn into_u8(&self) -> Result<Vec<u8>, String> {
-		bincode::serialize(self).map_err(|e| e.to_string())
+		codec::encode(self)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::SocketAddr;
+	use proptest::prelude::*;
+	use proptest::collection::vec;
+	use proptest::option;
+	use crate::proto_rtc::tests::{ id, candidate };
+	use crate::id::Metric;
+	use crate::topology::Slots;
+	use super::{ WebSocketData, IceServer, TurnCredentials, Neighbourhood };
+
+	fn addr() -> impl Strategy<Value = SocketAddr> {
+		prop_oneof![
+			(any::<[u8; 4]>(), any::<u16>()).prop_map(SocketAddr::from),
+			(any::<[u16; 8]>(), any::<u16>()).prop_map(SocketAddr::from)
+		]
+	}
+
+	fn ice_server() -> impl Strategy<Value = IceServer> {
+		(vec(any::<String>(), 0..3), option::of(any::<String>()), option::of(any::<String>()))
+			.prop_map(|(urls, username, credential)| IceServer { urls, username, credential })
+	}
+
+	fn neighbourhood() -> impl Strategy<Value = Neighbourhood> {
+		let slots = prop_oneof![Just(Slots::Sectors), Just(Slots::Nearest), Just(Slots::Buckets)];
+		let metric = prop_oneof![Just(Metric::Manhattan), Just(Metric::Torus)];
+		(slots, metric, any::<usize>()).prop_map(|(slots, metric, k)| Neighbourhood { slots, metric, k })
+	}
+
+	fn data() -> impl Strategy<Value = WebSocketData> {
+		prop_oneof![
+			(any::<String>(), option::of(addr())).prop_map(|(sdp, addr)| WebSocketData::OfferSDP(sdp, addr)),
+			(any::<String>(), addr()).prop_map(|(sdp, addr)| WebSocketData::AnswerSDP(sdp, addr)),
+			(candidate(), addr()).prop_map(|(candidate, addr)| WebSocketData::IceCandidate(candidate, addr)),
+			any::<String>().prop_map(WebSocketData::Message),
+			option::of(id()).prop_map(WebSocketData::Id),
+			vec(ice_server(), 0..4).prop_map(WebSocketData::IceServers),
+			option::of((ice_server(), any::<u64>()).prop_map(|(server, ttl)| TurnCredentials { server, ttl }))
+				.prop_map(WebSocketData::TurnCredentials),
+			(vec(any::<u8>(), 0..512), addr()).prop_map(|(data, addr)| WebSocketData::Relay(data, addr)),
+			addr().prop_map(WebSocketData::RelayClosed),
+			any::<String>().prop_map(WebSocketData::Notice),
+			any::<u64>().prop_map(WebSocketData::GoingAway),
+			option::of(any::<String>()).prop_map(WebSocketData::Resume),
+			(id(), any::<String>()).prop_map(|(id, token)| WebSocketData::Session(id, token)),
+			neighbourhood().prop_map(WebSocketData::Neighbourhood)
+		]
+	}
+
+	proptest! {
+		#[test]
+		fn round_trip(msg in data()) {
+			prop_assert_eq!(WebSocketData::from_u8(msg.into_u8().unwrap()).unwrap(), msg);
+		}
+
+		// an error, never a panic
+		#[test]
+		fn garbage(bytes in vec(any::<u8>(), 0..256)) {
+			if let Ok(msg) = WebSocketData::from_u8(bytes) {
+				prop_assert_eq!(WebSocketData::from_u8(msg.into_u8().unwrap()).unwrap(), msg);
+			}
+		}
+	}
+}
@@ -0,0 +1,217 @@
+use std::str::FromStr;
+use serde::{ Serialize, Deserialize };
+use crate::id::{ Id, Axe, Metric };
+
+// Which peers a node keeps as neighbours: the distance the greedy routing
+// reduces on every hop, and slots around the node each keeping its closest
+// peers. The server connects a newcomer to its closest peer with the same one
+
+pub const NEIGHBOURS_DFL: usize = 4; // k of nearest and buckets
+
+pub trait Topology {
+	fn distance(&self, a: Id, b: Id) -> u64;
+
+	// The slot of `peer` around `me`
+	fn slot(&self, me: Id, peer: Id) -> usize;
+
+	// Peers kept in a slot
+	fn capacity(&self) -> usize;
+
+	// Shown to the user
+	fn label(&self, slot: usize) -> String;
+
+	// The closest of the other peers to `to`, the first one on a tie
+	fn closest<T>(&self, to: Id, peers: impl IntoIterator<Item = (Id, T)>) -> Option<T> where Self: Sized {
+		peers.into_iter()
+			.filter(|(id, _)| *id != to)
+			.min_by_key(|(id, _)| self.distance(to, *id))
+			.map(|(_, peer)| peer)
+	}
+}
+
+// The closest peer on the top, left and right (`Metric::axe`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sectors(pub Metric);
+
+impl Topology for Sectors {
+	fn distance(&self, a: Id, b: Id) -> u64 {
+		self.0.distance(a, b)
+	}
+
+	fn slot(&self, me: Id, peer: Id) -> usize {
+		match self.0.axe(me, peer) {
+			Axe::Top => 0,
+			Axe::Left => 1,
+			Axe::Right => 2
+		}
+	}
+
+	fn capacity(&self) -> usize {
+		1
+	}
+
+	fn label(&self, slot: usize) -> String {
+		["Top", "Left", "Right"].get(slot).unwrap_or(&"Sector").to_string()
+	}
+}
+
+// The k closest peers, whatever their direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nearest {
+	pub metric: Metric,
+	pub k: usize
+}
+
+impl Topology for Nearest {
+	fn distance(&self, a: Id, b: Id) -> u64 {
+		self.metric.distance(a, b)
+	}
+
+	fn slot(&self, _me: Id, _peer: Id) -> usize {
+		0
+	}
+
+	fn capacity(&self) -> usize {
+		self.k
+	}
+
+	fn label(&self, _slot: usize) -> String {
+		"Near".to_string()
+	}
+}
+
+// Kademlia: the distance is the xor of the ids, and the k closest peers
+// are kept for every power of two of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buckets {
+	pub k: usize
+}
+
+impl Topology for Buckets {
+	fn distance(&self, a: Id, b: Id) -> u64 {
+		a.0 ^ b.0
+	}
+
+	fn slot(&self, me: Id, peer: Id) -> usize {
+		64 - self.distance(me, peer).leading_zeros() as usize
+	}
+
+	fn capacity(&self) -> usize {
+		self.k
+	}
+
+	fn label(&self, slot: usize) -> String {
+		format!("Bucket {}", slot)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Slots {
+	#[default]
+	Sectors,
+	Nearest,
+	Buckets
+}
+
+impl FromStr for Slots {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"sectors" => Ok(Slots::Sectors),
+			"nearest" => Ok(Slots::Nearest),
+			"buckets" => Ok(Slots::Buckets),
+			_ => Err(format!("unknown slots \"{}\"", s))
+		}
+	}
+}
+
+// The topology of a network, chosen by the server and announced to the clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Neighbourhood {
+	pub slots: Slots,
+	pub metric: Metric, // of sectors and nearest
+	pub k: usize // of nearest and buckets, at least 1
+}
+
+impl Default for Neighbourhood {
+	fn default() -> Self {
+		Neighbourhood { slots: Slots::Sectors, metric: Metric::Manhattan, k: NEIGHBOURS_DFL }
+	}
+}
+
+impl Neighbourhood {
+	fn with<R>(&self, f: impl FnOnce(&dyn Topology) -> R) -> R {
+		let k = self.k.max(1);
+		match self.slots {
+			Slots::Sectors => f(&Sectors(self.metric)),
+			Slots::Nearest => f(&Nearest { metric: self.metric, k }),
+			Slots::Buckets => f(&Buckets { k })
+		}
+	}
+}
+
+impl Topology for Neighbourhood {
+	fn distance(&self, a: Id, b: Id) -> u64 {
+		self.with(|topology| topology.distance(a, b))
+	}
+
+	fn slot(&self, me: Id, peer: Id) -> usize {
+		self.with(|topology| topology.slot(me, peer))
+	}
+
+	fn capacity(&self) -> usize {
+		self.with(|topology| topology.capacity())
+	}
+
+	fn label(&self, slot: usize) -> String {
+		self.with(|topology| topology.label(slot))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::id::{ Id, Metric };
+	use super::{ Topology, Sectors, Nearest, Buckets, Neighbourhood, Slots };
+
+	#[test]
+	fn slots() {
+		let me = Id::new(0, 0);
+		let sectors = Sectors(Metric::Manhattan);
+		assert_eq!(sectors.slot(me, Id::new(0, 5)), 0);
+		assert_eq!(sectors.slot(me, Id::new(-5, 0)), 1);
+		assert_eq!(sectors.label(sectors.slot(me, Id::new(5, 0))), "Right");
+
+		let nearest = Nearest { metric: Metric::Torus, k: 3 };
+		assert_eq!((nearest.slot(me, Id::new(0, 5)), nearest.slot(me, Id::new(-5, 0)), nearest.capacity()), (0, 0, 3));
+
+		let buckets = Buckets { k: 2 };
+		assert_eq!(buckets.distance(Id(0b1010), Id(0b0110)), 0b1100);
+		assert_eq!(buckets.slot(Id(0b1010), Id(0b0110)), 4);
+		assert_eq!(buckets.slot(Id(0b1010), Id(0b1011)), 1);
+		assert_eq!(buckets.slot(Id(0), Id(u64::MAX)), 64);
+
+		// the same as the topology it picks, never empty
+		let neighbourhood = Neighbourhood { slots: Slots::Buckets, k: 0, ..Neighbourhood::default() };
+		assert_eq!(neighbourhood.capacity(), 1);
+		assert_eq!(neighbourhood.distance(Id(0b1010), Id(0b0110)), 0b1100);
+		assert_eq!(Neighbourhood::default().label(2), "Right");
+		assert_eq!("Buckets".parse(), Ok(Slots::Buckets));
+		assert!("ring".parse::<Slots>().is_err());
+	}
+
+	#[test]
+	fn closest() {
+		let (a, b, c) = (Id::new(0, 0), Id::new(10, 10), Id::new(9, 9));
+		let peers = vec!((a, 'a'), (b, 'b'), (c, 'c'));
+		assert_eq!(Sectors(Metric::Manhattan).closest(b, peers.clone()), Some('c'));
+		assert_eq!(Buckets { k: 1 }.closest(Id(c.0 ^ 1), peers.clone()), Some('c'));
+		assert_eq!(Sectors(Metric::Manhattan).closest(a, vec!((a, 'a'))), None);
+		// around the edge
+		let (west, east) = (Id::new(i32::MIN, 0), Id::new(i32::MAX, 0));
+		assert_eq!(Nearest { metric: Metric::Torus, k: 1 }.closest(east, vec!((a, 'a'), (west, 'w'))), Some('w'));
+		assert_eq!(Nearest { metric: Metric::Manhattan, k: 1 }.closest(east, vec!((a, 'a'), (west, 'w'))), Some('a'));
+	}
+}
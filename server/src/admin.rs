@@ -0,0 +1,173 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use hyper::{ Body, Method, Request, Response, StatusCode };
+use hyper::header::{ AUTHORIZATION, WWW_AUTHENTICATE };
+use serde::Serialize;
+use tungstenite::protocol::frame::coding::CloseCode;
+use crossplatform::id::Id;
+use crossplatform::proto_ws::WebSocketData;
+use tracing::{ info, warn };
+
+use crate::process::{ send_to, close };
+use crate::admission;
+use crate::{ State, Peer, Result, text };
+
+// Operators api, every request need the `Authorization: Bearer <admin_token>` header
+//   GET    /admin/peers                    connected peers
+//   DELETE /admin/peers/<addr or name>     force a disconnection
+//   POST   /admin/notice                   broadcast the body to every client
+//   GET    /admin/matchmaking              true or false
+//   PUT    /admin/matchmaking              body: true or false
+//   GET    /admin/relay                    true or false
+//   PUT    /admin/relay                    body: true or false
+//   POST   /admin/admission                body: validity in seconds, a new admission token
+
+#[derive(Serialize)]
+struct PeerInfo {
+	id: u64,
+	name: String,
+	addr: SocketAddr,
+	connected_at: u64, // unix timestamps
+	last_activity: u64
+}
+
+fn timestamp(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl PeerInfo {
+	fn new(addr: SocketAddr, peer: &Peer) -> Self {
+		PeerInfo {
+			id: peer.id.0,
+			name: peer.id.to_name(),
+			addr,
+			connected_at: timestamp(peer.connected_at),
+			last_activity: timestamp(peer.last_activity)
+		}
+	}
+}
+
+// Do not leak the token length or content with the comparison time
+fn authorized(req: &Request<Body>, token: &str) -> bool {
+	let given = match req.headers().get(AUTHORIZATION)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|h| h.strip_prefix("Bearer ")) {
+		Some(given) => given.as_bytes(),
+		None => return false
+	};
+	let token = token.as_bytes();
+	let diff = given.iter()
+		.zip(token.iter())
+		.fold(given.len() ^ token.len(), |acc, (a, b)| acc | (a ^ b) as usize);
+	diff == 0
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<Body>> {
+	text(StatusCode::OK, "application/json", serde_json::to_string(value)?)
+}
+
+async fn switch(value: &std::sync::atomic::AtomicBool, req: Request<Body>) -> Result<Response<Body>> {
+	if req.method() == Method::PUT {
+		let body = hyper::body::to_bytes(req.into_body()).await?;
+		match std::str::from_utf8(&body).map(str::trim) {
+			Ok("true") => value.store(true, Ordering::Relaxed),
+			Ok("false") => value.store(false, Ordering::Relaxed),
+			_ => return text(StatusCode::BAD_REQUEST, "text/plain", "expected true or false\n".to_string())
+		}
+	}
+	json(&value.load(Ordering::Relaxed))
+}
+
+fn peers(state: &State) -> Result<Response<Body>> {
+	let peers = state.peers.lock().unwrap();
+	let list = peers.iter()
+		.map(|(addr, peer)| PeerInfo::new(*addr, peer))
+		.collect::<Vec<_>>();
+	json(&list)
+}
+
+// Dropping the sender end the connection once the close frame is sent
+fn disconnect(state: &State, target: &str) -> Result<Response<Body>> {
+	let mut peers = state.peers.lock().unwrap();
+	let addr = match target.parse::<SocketAddr>() {
+		Ok(addr) => Some(addr),
+		Err(_) => target.parse::<Id>().ok()
+			.and_then(|id| peers.iter().find(|(_, peer)| peer.id == id))
+			.map(|(addr, _)| *addr)
+	};
+	let peer = match addr.and_then(|addr| peers.remove(&addr)) {
+		Some(peer) => peer,
+		None => return text(StatusCode::NOT_FOUND, "text/plain", format!("no peer {}\n", target))
+	};
+	close(&peer.tx, CloseCode::Policy, "Disconnected by an administrator", &state.metrics);
+	info!(peer = %target, "disconnected by an administrator");
+	json(&PeerInfo::new(addr.unwrap(), &peer))
+}
+
+async fn notice(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+	let msg = match String::from_utf8(body.to_vec()) {
+		Ok(msg) if !msg.trim().is_empty() => WebSocketData::Notice(msg.trim().to_string()),
+		_ => return text(StatusCode::BAD_REQUEST, "text/plain", "expected an utf8 message\n".to_string())
+	};
+	let peers = state.peers.lock().unwrap();
+	for peer in peers.values() {
+		send_to(&peer.tx, &msg, &state.metrics);
+	}
+	info!(peers = peers.len(), "notice broadcasted");
+	json(&peers.len())
+}
+
+async fn admission(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+	let secret = match &state.config.admission_secret {
+		Some(secret) => secret,
+		None => return text(StatusCode::NOT_FOUND, "text/plain", "no admission secret configured\n".to_string())
+	};
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+	match std::str::from_utf8(&body).ok().and_then(|ttl| ttl.trim().parse::<u64>().ok()) {
+		Some(ttl) if ttl > 0 => json(&admission::token(secret, ttl, SystemTime::now())),
+		_ => text(StatusCode::BAD_REQUEST, "text/plain", "expected a validity in seconds\n".to_string())
+	}
+}
+
+pub async fn handler(state: Arc<State>, req: Request<Body>) -> Result<Response<Body>> {
+	let token = match &state.config.admin_token {
+		Some(token) => token,
+		None => return text(StatusCode::NOT_FOUND, "text/plain", "admin api disabled\n".to_string())
+	};
+	if !authorized(&req, token) {
+		warn!(uri = %req.uri(), "unauthorized admin request");
+		let mut rsp = text(StatusCode::UNAUTHORIZED, "text/plain", "unauthorized\n".to_string())?;
+		rsp.headers_mut().insert(WWW_AUTHENTICATE, "Bearer".parse()?);
+		return Ok(rsp);
+	}
+	let path = req.uri().path().trim_start_matches("/admin").to_string();
+	match (req.method(), path.as_str()) {
+		(&Method::GET, "/peers") => peers(&state),
+		(&Method::DELETE, target) if target.starts_with("/peers/") => disconnect(&state, &target["/peers/".len()..]),
+		(&Method::POST, "/notice") => notice(&state, req).await,
+		(&Method::GET, "/matchmaking") | (&Method::PUT, "/matchmaking") => switch(&state.matchmaking, req).await,
+		(&Method::GET, "/relay") | (&Method::PUT, "/relay") => switch(state.relay.enabled(), req).await,
+		(&Method::POST, "/admission") => admission(&state, req).await,
+		_ => text(StatusCode::NOT_FOUND, "text/plain", "not found\n".to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{ Body, Request };
+	use super::authorized;
+
+	#[test]
+	fn token() {
+		let req = |header: &str| Request::builder().header("Authorization", header).body(Body::empty()).unwrap();
+		assert!(authorized(&req("Bearer 0123456789abcdef"), "0123456789abcdef"));
+		assert!(!authorized(&req("Bearer 0123456789abcdeF"), "0123456789abcdef"));
+		assert!(!authorized(&req("Bearer 0123456789abcde"), "0123456789abcdef"));
+		assert!(!authorized(&req("Bearer 0123456789abcdef0"), "0123456789abcdef"));
+		assert!(!authorized(&req("0123456789abcdef"), "0123456789abcdef"));
+		assert!(!authorized(&Request::new(Body::empty()), "0123456789abcdef"));
+	}
+}
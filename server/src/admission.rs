@@ -0,0 +1,157 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+use hmac::{ Hmac, Mac, NewMac };
+use sha1::Sha1;
+use hyper::{ Body, Request };
+use hyper::header::{ ORIGIN, SEC_WEBSOCKET_PROTOCOL };
+use crate::config::Config;
+
+// Who can open a websocket:
+// - the Origin header has to be one of `allowed_origins` (any if empty)
+// - with an admission secret, a valid token has to be given either in the
+//   url (`?token=<token>`) or as a subprotocol (`token.<token>`)
+// token = "<expiry unix timestamp>.<base64url(hmac-sha1(secret, expiry))>",
+// only made of characters allowed in a subprotocol
+
+pub const TOKEN_PROTOCOL: &str = "token.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+	Origin,
+	Token
+}
+
+impl Refused {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Refused::Origin => "origin",
+			Refused::Token => "token"
+		}
+	}
+}
+
+pub fn timestamp(now: SystemTime) -> u64 {
+	now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn mac(secret: &[u8], payload: &str) -> Hmac<Sha1> {
+	// hmac accept keys of any size
+	let mut mac = Hmac::<Sha1>::new_varkey(secret).unwrap();
+	mac.update(payload.as_bytes());
+	mac
+}
+
+// base64url(hmac-sha1(secret, payload)), usable in urls and subprotocols
+pub fn sign(secret: &[u8], payload: &str) -> String {
+	base64::encode_config(mac(secret, payload).finalize().into_bytes(), base64::URL_SAFE_NO_PAD)
+}
+
+pub fn signed(secret: &[u8], payload: &str, signature: &str) -> bool {
+	match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+		Ok(signature) => mac(secret, payload).verify(&signature).is_ok(),
+		Err(_) => false
+	}
+}
+
+pub fn token(secret: &str, ttl: u64, now: SystemTime) -> String {
+	let expiry = (timestamp(now) + ttl).to_string();
+	let signature = sign(secret.as_bytes(), &expiry);
+	format!("{}.{}", expiry, signature)
+}
+
+pub fn verify(secret: &str, token: &str, now: SystemTime) -> bool {
+	let mut parts = token.splitn(2, '.');
+	let (expiry, signature) = match (parts.next(), parts.next()) {
+		(Some(expiry), Some(signature)) => (expiry, signature),
+		_ => return false
+	};
+	match expiry.parse::<u64>() {
+		Ok(time) => time >= timestamp(now) && signed(secret.as_bytes(), expiry, signature),
+		Err(_) => false
+	}
+}
+
+fn query_token(req: &Request<Body>) -> Option<&str> {
+	req.uri().query()?
+		.split('&')
+		.find_map(|param| param.strip_prefix("token="))
+}
+
+// The subprotocols offered by the client
+pub fn protocols(req: &Request<Body>) -> impl Iterator<Item = &str> {
+	req.headers().get_all(SEC_WEBSOCKET_PROTOCOL).iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.map(str::trim)
+}
+
+// Check the upgrade request, returns the token subprotocol to select if it was used
+pub fn check<'a>(config: &Config, req: &'a Request<Body>, now: SystemTime) -> Result<Option<&'a str>, Refused> {
+	if !config.allowed_origins.is_empty() {
+		let origin = req.headers().get(ORIGIN).and_then(|origin| origin.to_str().ok());
+		match origin {
+			Some(origin) if config.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) => (),
+			_ => return Err(Refused::Origin)
+		}
+	}
+	let secret = match &config.admission_secret {
+		Some(secret) => secret,
+		None => return Ok(None)
+	};
+	if let Some(token) = query_token(req) {
+		if verify(secret, token, now) {
+			return Ok(None);
+		}
+	}
+	protocols(req)
+		.find(|protocol| matches!(protocol.strip_prefix(TOKEN_PROTOCOL), Some(token) if verify(secret, token, now)))
+		.map(Some)
+		.ok_or(Refused::Token)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, UNIX_EPOCH };
+	use hyper::{ Body, Request };
+	use crate::config::Config;
+	use super::{ token, verify, check, Refused };
+
+	#[test]
+	fn tokens() {
+		let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+		let valid = token("north", 60, now);
+		assert!(valid.starts_with("1600000060."));
+		assert!(verify("north", &valid, now));
+		assert!(verify("north", &valid, now + Duration::from_secs(60)));
+		assert!(!verify("north", &valid, now + Duration::from_secs(61)));
+		assert!(!verify("south", &valid, now));
+		assert!(!verify("north", &valid.replacen("1600000060", "1600000099", 1), now));
+		assert!(!verify("north", "1600000060", now));
+		assert!(!verify("north", "", now));
+	}
+
+	#[test]
+	fn upgrade() {
+		let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+		let valid = token("north", 60, now);
+		let mut config = Config::default();
+		assert_eq!(check(&config, &Request::new(Body::empty()), now), Ok(None));
+
+		config.allowed_origins = vec!("https://chat.example.com".to_string());
+		let req = Request::builder().header("Origin", "https://evil.example.com").body(Body::empty()).unwrap();
+		assert_eq!(check(&config, &req, now), Err(Refused::Origin));
+		let req = Request::builder().header("Origin", "https://chat.example.com").body(Body::empty()).unwrap();
+		assert_eq!(check(&config, &req, now), Ok(None));
+		assert_eq!(check(&config, &Request::new(Body::empty()), now), Err(Refused::Origin));
+
+		config.allowed_origins = vec!();
+		config.admission_secret = Some("north".to_string());
+		assert_eq!(check(&config, &Request::new(Body::empty()), now), Err(Refused::Token));
+		let req = Request::builder().uri(format!("/?a=b&token={}", valid)).body(Body::empty()).unwrap();
+		assert_eq!(check(&config, &req, now), Ok(None));
+		let protocol = format!("token.{}", valid);
+		let req = Request::builder().header("Sec-WebSocket-Protocol", format!("chat, {}", protocol)).body(Body::empty()).unwrap();
+		assert_eq!(check(&config, &req, now), Ok(Some(protocol.as_str())));
+		let req = Request::builder().header("Sec-WebSocket-Protocol", "token.1600000060.AAAA").body(Body::empty()).unwrap();
+		assert_eq!(check(&config, &req, now), Err(Refused::Token));
+	}
+}
@@ -0,0 +1,750 @@
+use std::fmt;
+use std::fs;
+use std::net::{ IpAddr, SocketAddr };
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use serde::Deserialize;
+use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
+pub use crossplatform::proto_ws::IceServer;
+pub use crossplatform::id::Metric;
+pub use crossplatform::topology::{ Neighbourhood, Slots };
+
+// Server configuration, resolved in this order (last one wins):
+// defaults -> TOML file -> environment -> command line flags
+
+const BIND_DFL: &str = "127.0.0.1:8088";
+const STATIC_FOLDER_DFL: &str = "./static/";
+const STUN_DFL: &str = "stun:stun.l.google.com:19302";
+const MAX_PEERS_DFL: usize = 1024;
+const HANDSHAKE_TIMEOUT_DFL: u64 = 10;
+const IDLE_TIMEOUT_DFL: u64 = 300;
+const DRAIN_TIMEOUT_DFL: u64 = 10;
+const RECONNECT_WINDOW_DFL: u64 = 10;
+const TURN_TTL_DFL: u64 = 3600;
+const RELAY_QUOTA_DFL: u64 = 10 * 1024 * 1024;
+const ADMIN_TOKEN_MIN: usize = 16;
+const MAX_PEERS_PER_IP_DFL: usize = 16;
+const SESSION_TTL_DFL: u64 = 24 * 3600;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "server", about = "Rendezvous server for the p2p chat")]
+pub struct Opt {
+	/// TOML configuration file
+	#[structopt(short, long, env = "P2P_CONFIG", parse(from_os_str))]
+	pub config: Option<PathBuf>,
+
+	/// Address to listen on, can be repeated (replace the configured ones)
+	#[structopt(short, long, env = "P2P_BIND", use_delimiter = true)]
+	pub bind: Vec<SocketAddr>,
+
+	/// Override the ip of every bind address
+	#[structopt(long, env = "P2P_ADDR")]
+	pub addr: Option<IpAddr>,
+
+	/// Override the port of every bind address
+	#[structopt(short, long, env = "PORT")]
+	pub port: Option<u16>,
+
+	/// Folder containing index.html and the compiled wasm
+	#[structopt(long, env = "P2P_STATIC_FILES", parse(from_os_str))]
+	pub static_dir: Option<PathBuf>,
+
+	/// ICE server handed to the clients as [username:credential@]url, can be repeated (replace the configured ones)
+	#[structopt(long = "ice-server", env = "P2P_ICE_SERVERS", use_delimiter = true, hide_env_values = true, parse(from_str = ice_server))]
+	pub ice_servers: Vec<IceServer>,
+
+	/// Shared secret with the TURN server (replace the configured one)
+	#[structopt(long, env = "P2P_TURN_SECRET", hide_env_values = true)]
+	pub turn_secret: Option<String>,
+
+	/// Forward the data between two clients when WebRTC cannot connect them: true or false
+	#[structopt(long, env = "P2P_RELAY")]
+	pub relay: Option<bool>,
+
+	/// Bytes a client can send through the relay
+	#[structopt(long, env = "P2P_RELAY_QUOTA")]
+	pub relay_quota: Option<u64>,
+
+	/// Origin allowed to open a websocket (ex: https://chat.example.com), can be repeated, any if none
+	#[structopt(long = "allowed-origin", env = "P2P_ALLOWED_ORIGINS", use_delimiter = true)]
+	pub allowed_origins: Vec<String>,
+
+	/// Secret signing the admission tokens, the clients need one to connect
+	#[structopt(long, env = "P2P_ADMISSION_SECRET", hide_env_values = true)]
+	pub admission_secret: Option<String>,
+
+	/// Secret signing the session resume tokens, random if none (the sessions do not survive a restart)
+	#[structopt(long, env = "P2P_SESSION_SECRET", hide_env_values = true)]
+	pub session_secret: Option<String>,
+
+	/// Bearer token of the /admin api, the api is disabled without it
+	#[structopt(long, env = "P2P_ADMIN_TOKEN", hide_env_values = true)]
+	pub admin_token: Option<String>,
+
+	/// Neighbours kept by the clients: sectors (top, left, right), nearest or buckets (Kademlia)
+	#[structopt(long, env = "P2P_SLOTS")]
+	pub slots: Option<Slots>,
+
+	/// Distance between the ids for sectors and nearest: manhattan or torus (wraps around)
+	#[structopt(long, env = "P2P_METRIC")]
+	pub metric: Option<Metric>,
+
+	/// Neighbours kept by nearest, and in each bucket
+	#[structopt(long, env = "P2P_NEIGHBOURS")]
+	pub neighbours: Option<usize>,
+
+	/// Maximum number of simultaneously connected peers
+	#[structopt(long, env = "P2P_MAX_PEERS")]
+	pub max_peers: Option<usize>,
+
+	/// Maximum number of simultaneous connections from the same ip
+	#[structopt(long, env = "P2P_MAX_PEERS_PER_IP")]
+	pub max_peers_per_ip: Option<usize>,
+
+	/// Seconds between the http upgrade and the first websocket frame
+	#[structopt(long, env = "P2P_HANDSHAKE_TIMEOUT")]
+	pub handshake_timeout: Option<u64>,
+
+	/// Seconds without any frame from a client before dropping it
+	#[structopt(long, env = "P2P_IDLE_TIMEOUT")]
+	pub idle_timeout: Option<u64>,
+
+	/// Seconds to wait for the websockets to close on shutdown
+	#[structopt(long, env = "P2P_DRAIN_TIMEOUT")]
+	pub drain_timeout: Option<u64>,
+
+	/// Seconds over which the clients spread their reconnection after a shutdown
+	#[structopt(long, env = "P2P_RECONNECT_WINDOW")]
+	pub reconnect_window: Option<u64>,
+
+	/// OfferSDP frames a connection can send, as rate/burst (ex: 1/10 is 10 at once then 1 per second)
+	#[structopt(long, env = "P2P_OFFER_LIMIT")]
+	pub offer_limit: Option<Limit>,
+
+	/// Message frames a connection can send, as rate/burst
+	#[structopt(long, env = "P2P_MESSAGE_LIMIT")]
+	pub message_limit: Option<Limit>,
+
+	/// Other frames a connection can send, as rate/burst
+	#[structopt(long, env = "P2P_OTHER_LIMIT")]
+	pub other_limit: Option<Limit>,
+
+	/// error, warn, info, debug or trace
+	#[structopt(short, long, env = "P2P_LOG")]
+	pub log_level: Option<LogLevel>,
+
+	/// Per module filter, take precedence over the log level (ex: "server=debug,hyper=warn")
+	#[structopt(long, env = "RUST_LOG")]
+	pub log_filter: Option<String>,
+
+	/// text or json
+	#[structopt(long, env = "P2P_LOG_FORMAT")]
+	pub log_format: Option<LogFormat>,
+}
+
+// The credentials of a TURN server prefix its url: user:password@turn:example.com
+fn ice_server(arg: &str) -> IceServer {
+	match arg.rsplit_once('@') {
+		Some((credentials, url)) => {
+			let (username, credential) = credentials.split_once(':').unwrap_or((credentials, ""));
+			IceServer {
+				urls: vec!(url.to_string()),
+				username: Some(username.to_string()),
+				credential: Some(credential.to_string())
+			}
+		},
+		None => IceServer::stun(arg)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+	Error,
+	Warn,
+	Info,
+	Debug,
+	Trace
+}
+
+impl LogLevel {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			LogLevel::Error => "error",
+			LogLevel::Warn => "warn",
+			LogLevel::Info => "info",
+			LogLevel::Debug => "debug",
+			LogLevel::Trace => "trace"
+		}
+	}
+}
+
+impl FromStr for LogLevel {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"error" => Ok(LogLevel::Error),
+			"warn" => Ok(LogLevel::Warn),
+			"info" => Ok(LogLevel::Info),
+			"debug" => Ok(LogLevel::Debug),
+			"trace" => Ok(LogLevel::Trace),
+			_ => Err(format!("unknown log level \"{}\"", s))
+		}
+	}
+}
+
+// In seconds in the file
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+	pub handshake: u64, // http upgrade to first websocket frame
+	pub idle: u64, // no frame from the client, not even a pong
+	pub drain: u64, // on shutdown, wait for the websockets to close
+	pub reconnect: u64 // on shutdown, the clients reconnect at a random time in this window
+}
+
+impl Default for Timeouts {
+	fn default() -> Self {
+		Timeouts {
+			handshake: HANDSHAKE_TIMEOUT_DFL,
+			idle: IDLE_TIMEOUT_DFL,
+			drain: DRAIN_TIMEOUT_DFL,
+			reconnect: RECONNECT_WINDOW_DFL
+		}
+	}
+}
+
+impl Timeouts {
+	pub fn handshake(&self) -> Duration {
+		Duration::from_secs(self.handshake)
+	}
+
+	pub fn idle(&self) -> Duration {
+		Duration::from_secs(self.idle)
+	}
+
+	pub fn drain(&self) -> Duration {
+		Duration::from_secs(self.drain)
+	}
+}
+
+// TURN servers using the REST api shared secret, every client get its own
+// time limited credentials
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurnConfig {
+	pub urls: Vec<String>,
+	#[serde(default)]
+	pub secret: String,
+	#[serde(default = "turn_ttl_dfl")]
+	pub ttl: u64 // seconds
+}
+
+fn turn_ttl_dfl() -> u64 {
+	TURN_TTL_DFL
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	Text,
+	Json
+}
+
+impl FromStr for LogFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"text" => Ok(LogFormat::Text),
+			"json" => Ok(LogFormat::Json),
+			_ => Err(format!("unknown log format \"{}\"", s))
+		}
+	}
+}
+
+// The server forward the data when two clients cannot use WebRTC
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+	pub enabled: bool,
+	pub quota: u64 // bytes a client can send through the relay
+}
+
+// A reconnecting client get its Id back with a resume token
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+	pub secret: Option<String>,
+	pub ttl: u64 // seconds
+}
+
+impl Default for SessionConfig {
+	fn default() -> Self {
+		SessionConfig {
+			secret: None,
+			ttl: SESSION_TTL_DFL
+		}
+	}
+}
+
+// Token bucket: `burst` frames at once then `rate` frames per second
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+	pub rate: f64,
+	pub burst: u32
+}
+
+impl FromStr for Limit {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("\"{}\" should look like rate/burst", s);
+		let (rate, burst) = s.split_once('/').ok_or_else(invalid)?;
+		Ok(Limit {
+			rate: rate.trim().parse().map_err(|_| invalid())?,
+			burst: burst.trim().parse().map_err(|_| invalid())?
+		})
+	}
+}
+
+// Frames a single connection can send, by kind of WebSocketData
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+	pub offer: Limit, // OfferSDP, each one may start a matchmaking
+	pub message: Limit, // Message, broadcasted to everyone
+	pub other: Limit
+}
+
+impl Default for RateLimits {
+	fn default() -> Self {
+		RateLimits {
+			offer: Limit { rate: 1.0, burst: 10 },
+			message: Limit { rate: 2.0, burst: 20 },
+			other: Limit { rate: 50.0, burst: 200 }
+		}
+	}
+}
+
+impl Default for RelayConfig {
+	fn default() -> Self {
+		RelayConfig {
+			enabled: false,
+			quota: RELAY_QUOTA_DFL
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub bind: Vec<SocketAddr>,
+	pub static_dir: PathBuf,
+	pub ice_servers: Vec<IceServer>,
+	pub turn: Option<TurnConfig>,
+	pub relay: RelayConfig,
+	pub admin_token: Option<String>,
+	pub allowed_origins: Vec<String>,
+	pub admission_secret: Option<String>,
+	pub session: SessionConfig,
+	pub neighbourhood: Neighbourhood,
+	pub max_peers: usize,
+	pub max_peers_per_ip: usize,
+	pub rate_limits: RateLimits,
+	pub timeouts: Timeouts,
+	pub log_level: LogLevel,
+	pub log_filter: Option<String>,
+	pub log_format: LogFormat
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			bind: vec!(BIND_DFL.parse().unwrap()),
+			static_dir: PathBuf::from(STATIC_FOLDER_DFL),
+			ice_servers: vec!(IceServer::stun(STUN_DFL)),
+			turn: None,
+			relay: RelayConfig::default(),
+			admin_token: None,
+			allowed_origins: vec!(),
+			admission_secret: None,
+			session: SessionConfig::default(),
+			neighbourhood: Neighbourhood::default(),
+			max_peers: MAX_PEERS_DFL,
+			max_peers_per_ip: MAX_PEERS_PER_IP_DFL,
+			rate_limits: RateLimits::default(),
+			timeouts: Timeouts::default(),
+			log_level: LogLevel::Info,
+			log_filter: None,
+			log_format: LogFormat::Text
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Read(PathBuf, std::io::Error),
+	Parse(PathBuf, toml::de::Error),
+	Invalid(String)
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+			ConfigError::Parse(path, e) => write!(f, "cannot parse {}: {}", path.display(), e),
+			ConfigError::Invalid(e) => write!(f, "{}", e)
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+	// Parse the command line (and the env) then load the config file if any
+	pub fn load() -> Result<Self, ConfigError> {
+		Self::from_opt(Opt::from_args())
+	}
+
+	pub fn from_opt(opt: Opt) -> Result<Self, ConfigError> {
+		let mut config = match &opt.config {
+			Some(path) => {
+				let content = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+				toml::from_str(&content).map_err(|e| ConfigError::Parse(path.clone(), e))?
+			},
+			None => Config::default()
+		};
+		config.merge(opt);
+		config.validate()?;
+		Ok(config)
+	}
+
+	fn merge(&mut self, opt: Opt) {
+		if !opt.bind.is_empty() {
+			self.bind = opt.bind;
+		}
+		for bind in self.bind.iter_mut() {
+			if let Some(addr) = opt.addr {
+				bind.set_ip(addr);
+			}
+			if let Some(port) = opt.port {
+				bind.set_port(port);
+			}
+		}
+		if let Some(static_dir) = opt.static_dir {
+			self.static_dir = static_dir;
+		}
+		if !opt.ice_servers.is_empty() {
+			self.ice_servers = opt.ice_servers;
+		}
+		if let Some(secret) = opt.turn_secret {
+			let turn = self.turn.get_or_insert(TurnConfig { urls: vec!(), secret: String::new(), ttl: TURN_TTL_DFL });
+			turn.secret = secret;
+		}
+		if let Some(enabled) = opt.relay {
+			self.relay.enabled = enabled;
+		}
+		if let Some(quota) = opt.relay_quota {
+			self.relay.quota = quota;
+		}
+		if !opt.allowed_origins.is_empty() {
+			self.allowed_origins = opt.allowed_origins;
+		}
+		if let Some(secret) = opt.admission_secret {
+			self.admission_secret = Some(secret);
+		}
+		if let Some(secret) = opt.session_secret {
+			self.session.secret = Some(secret);
+		}
+		if let Some(token) = opt.admin_token {
+			self.admin_token = Some(token);
+		}
+		if let Some(slots) = opt.slots {
+			self.neighbourhood.slots = slots;
+		}
+		if let Some(metric) = opt.metric {
+			self.neighbourhood.metric = metric;
+		}
+		if let Some(k) = opt.neighbours {
+			self.neighbourhood.k = k;
+		}
+		if let Some(max_peers) = opt.max_peers {
+			self.max_peers = max_peers;
+		}
+		if let Some(max_peers_per_ip) = opt.max_peers_per_ip {
+			self.max_peers_per_ip = max_peers_per_ip;
+		}
+		if let Some(handshake) = opt.handshake_timeout {
+			self.timeouts.handshake = handshake;
+		}
+		if let Some(idle) = opt.idle_timeout {
+			self.timeouts.idle = idle;
+		}
+		if let Some(drain) = opt.drain_timeout {
+			self.timeouts.drain = drain;
+		}
+		if let Some(reconnect) = opt.reconnect_window {
+			self.timeouts.reconnect = reconnect;
+		}
+		if let Some(offer) = opt.offer_limit {
+			self.rate_limits.offer = offer;
+		}
+		if let Some(message) = opt.message_limit {
+			self.rate_limits.message = message;
+		}
+		if let Some(other) = opt.other_limit {
+			self.rate_limits.other = other;
+		}
+		if let Some(log_level) = opt.log_level {
+			self.log_level = log_level;
+		}
+		if let Some(log_filter) = opt.log_filter {
+			self.log_filter = Some(log_filter);
+		}
+		if let Some(log_format) = opt.log_format {
+			self.log_format = log_format;
+		}
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+		if self.bind.is_empty() {
+			return invalid("bind: at least one address is needed".to_string());
+		}
+		if !self.static_dir.join("index.html").is_file() {
+			return invalid(format!("static_dir: cannot find index.html in {}", self.static_dir.display()));
+		}
+		for server in self.ice_servers.iter() {
+			if server.urls.is_empty() {
+				return invalid("ice_servers: an entry has no url".to_string());
+			}
+			for url in server.urls.iter() {
+				let turn = if url.starts_with("stun:") || url.starts_with("stuns:") {
+					false
+				} else if url.starts_with("turn:") || url.starts_with("turns:") {
+					true
+				} else {
+					return invalid(format!("ice_servers: \"{}\" is not a stun: or turn: url", url));
+				};
+				if turn && (server.username.is_none() || server.credential.is_none()) {
+					return invalid(format!("ice_servers: \"{}\" needs a username and a credential", url));
+				}
+			}
+		}
+		if let Some(turn) = &self.turn {
+			if turn.urls.is_empty() {
+				return invalid("turn: at least one url is needed".to_string());
+			}
+			if let Some(url) = turn.urls.iter().find(|url| !url.starts_with("turn:") && !url.starts_with("turns:")) {
+				return invalid(format!("turn: \"{}\" is not a turn: url", url));
+			}
+			if turn.secret.is_empty() {
+				return invalid("turn: the secret is missing".to_string());
+			}
+			if turn.ttl == 0 {
+				return invalid("turn: ttl should be at least 1 second".to_string());
+			}
+		}
+		if self.relay.enabled && self.relay.quota == 0 {
+			return invalid("relay: quota should be at least 1 byte".to_string());
+		}
+		if matches!(&self.admin_token, Some(token) if token.len() < ADMIN_TOKEN_MIN) {
+			return invalid(format!("admin_token: should be at least {} characters", ADMIN_TOKEN_MIN));
+		}
+		// compared with the Origin header: scheme://host[:port]
+		if let Some(origin) = self.allowed_origins.iter()
+			.find(|origin| !(origin.starts_with("http://") || origin.starts_with("https://")) || origin.ends_with('/')) {
+			return invalid(format!("allowed_origins: \"{}\" should look like https://host[:port]", origin));
+		}
+		if matches!(&self.admission_secret, Some(secret) if secret.is_empty()) {
+			return invalid("admission_secret: should not be empty".to_string());
+		}
+		if matches!(&self.session.secret, Some(secret) if secret.is_empty()) || self.session.ttl == 0 {
+			return invalid("session: the secret should not be empty and the ttl at least 1 second".to_string());
+		}
+		if self.max_peers == 0 {
+			return invalid("max_peers: should be at least 1".to_string());
+		}
+		if self.max_peers_per_ip == 0 {
+			return invalid("max_peers_per_ip: should be at least 1".to_string());
+		}
+		if self.neighbourhood.k == 0 {
+			return invalid("neighbourhood.k: should be at least 1".to_string());
+		}
+		let limits = &self.rate_limits;
+		for (name, limit) in [("offer", limits.offer), ("message", limits.message), ("other", limits.other)].iter() {
+			if !(limit.rate > 0.0 && limit.rate.is_finite()) || limit.burst == 0 {
+				return invalid(format!("rate_limits.{}: rate and burst should be positive", name));
+			}
+		}
+		if self.timeouts.handshake == 0 || self.timeouts.idle == 0 {
+			return invalid("timeouts: should be at least 1 second".to_string());
+		}
+		if let Err(e) = EnvFilter::try_new(self.log_filter()) {
+			return invalid(format!("log_filter: {}", e));
+		}
+		Ok(())
+	}
+
+	fn log_filter(&self) -> &str {
+		self.log_filter.as_deref().unwrap_or_else(|| self.log_level.as_str())
+	}
+}
+
+pub fn init_logs(config: &Config) {
+	let subscriber = tracing_subscriber::fmt()
+		.with_env_filter(EnvFilter::new(config.log_filter()));
+	match config.log_format {
+		LogFormat::Text => subscriber.init(),
+		LogFormat::Json => subscriber.json().init()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ Config, Opt, LogLevel, IceServer, TurnConfig };
+	use structopt::StructOpt;
+
+	fn from_toml(content: &str) -> Config {
+		toml::from_str(content).expect("invalid toml")
+	}
+
+	#[test]
+	fn file() {
+		let config = from_toml(r#"
+			bind = ["0.0.0.0:80", "[::]:80"]
+			max_peers = 12
+			log_level = "debug"
+
+			[neighbourhood]
+			metric = "torus"
+
+			[timeouts]
+			idle = 60
+
+			[rate_limits]
+			offer = { rate = 0.5, burst = 3 }
+
+			[[ice_servers]]
+			urls = ["turn:turn.example.com:3478"]
+			username = "user"
+			credential = "pass"
+
+			[turn]
+			urls = ["turns:turn.example.com:5349"]
+			secret = "secret"
+		"#);
+		assert_eq!(config.bind.len(), 2);
+		assert_eq!(config.max_peers, 12);
+		assert_eq!(config.neighbourhood.metric, super::Metric::Torus);
+		assert_eq!(config.neighbourhood.slots, super::Slots::Sectors);
+		assert_eq!(config.log_level, LogLevel::Debug);
+		assert_eq!(config.timeouts.idle, 60);
+		assert_eq!(config.rate_limits.offer.burst, 3);
+		assert_eq!(config.rate_limits.message, super::RateLimits::default().message);
+		assert_eq!(config.timeouts.handshake, super::HANDSHAKE_TIMEOUT_DFL);
+		assert_eq!(config.ice_servers[0].username, Some("user".to_string()));
+		assert_eq!(config.turn.map(|turn| turn.ttl), Some(super::TURN_TTL_DFL));
+		assert!(toml::from_str::<Config>("unknown = 1").is_err());
+	}
+
+	#[test]
+	fn overrides() {
+		let mut config = from_toml("bind = [\"127.0.0.1:80\", \"127.0.0.2:80\"]");
+		let opt = Opt::from_iter(&["server", "--port", "9000", "--ice-server", "stun:a:1,stun:b:2", "-l", "warn"]);
+		config.merge(opt);
+		assert!(config.bind.iter().all(|addr| addr.port() == 9000));
+		assert_eq!(config.ice_servers, vec!(IceServer::stun("stun:a:1"), IceServer::stun("stun:b:2")));
+		assert_eq!(config.log_level, LogLevel::Warn);
+		assert_eq!(config.neighbourhood, super::Neighbourhood::default());
+		config.merge(Opt::from_iter(&["server", "--ice-server", "user:pass:word@turn:c:3", "--idle-timeout", "60", "--handshake-timeout", "5"]));
+		assert_eq!(config.ice_servers, vec!(IceServer {
+			urls: vec!("turn:c:3".to_string()),
+			username: Some("user".to_string()),
+			credential: Some("pass:word".to_string())
+		}));
+		assert_eq!((config.timeouts.idle, config.timeouts.handshake), (60, 5));
+		config.merge(Opt::from_iter(&["server", "--drain-timeout", "3", "--reconnect-window", "30"]));
+		assert_eq!((config.timeouts.drain, config.timeouts.reconnect), (3, 30));
+		assert!(!config.relay.enabled);
+		config.merge(Opt::from_iter(&["server", "--relay", "true", "--relay-quota", "1024"]));
+		assert_eq!(config.relay, super::RelayConfig { enabled: true, quota: 1024 });
+		config.merge(Opt::from_iter(&["server", "--offer-limit", "0.5/3"]));
+		assert_eq!(config.rate_limits.offer, super::Limit { rate: 0.5, burst: 3 });
+		assert_eq!(config.rate_limits.other, super::RateLimits::default().other);
+		assert!(Opt::from_iter_safe(&["server", "--message-limit", "2"]).is_err());
+		config.merge(Opt::from_iter(&["server", "--metric", "torus", "--slots", "buckets", "--neighbours", "8"]));
+		assert_eq!((config.neighbourhood.metric, config.neighbourhood.slots, config.neighbourhood.k), (super::Metric::Torus, super::Slots::Buckets, 8));
+
+		let opt = Opt::from_iter(&["server", "-b", "10.0.0.1:1234"]);
+		config.merge(opt);
+		assert_eq!(config.bind, vec!("10.0.0.1:1234".parse().unwrap()));
+	}
+
+	#[test]
+	fn validation() {
+		let mut config = Config {
+			static_dir: [env!("CARGO_MANIFEST_DIR"), "..", "static"].iter().collect(),
+			..Config::default()
+		};
+		assert!(config.validate().is_ok());
+
+		config.ice_servers = vec!(IceServer::stun("turn:example.com"));
+		assert!(config.validate().is_err());
+		config.ice_servers = vec!(IceServer::stun("http://example.com"));
+		assert!(config.validate().is_err());
+		config.ice_servers = vec!();
+
+		config.turn = Some(TurnConfig { urls: vec!("turn:example.com".to_string()), secret: String::new(), ttl: 10 });
+		assert!(config.validate().is_err());
+		config.turn.as_mut().unwrap().secret = "secret".to_string();
+		assert!(config.validate().is_ok());
+
+		config.max_peers = 0;
+		assert!(config.validate().is_err());
+		config.max_peers = 1;
+		config.max_peers_per_ip = 0;
+		assert!(config.validate().is_err());
+		config.max_peers_per_ip = 1;
+		config.neighbourhood.k = 0;
+		assert!(config.validate().is_err());
+		config.neighbourhood.k = 1;
+
+		config.rate_limits.offer.rate = 0.0;
+		assert!(config.validate().is_err());
+		config.rate_limits.offer.rate = 0.5;
+		config.rate_limits.message.burst = 0;
+		assert!(config.validate().is_err());
+		config.rate_limits.message.burst = 1;
+
+		config.allowed_origins = vec!("https://chat.example.com/".to_string());
+		assert!(config.validate().is_err());
+		config.allowed_origins = vec!("chat.example.com".to_string());
+		assert!(config.validate().is_err());
+		config.allowed_origins = vec!("https://chat.example.com".to_string(), "http://localhost:8088".to_string());
+		assert!(config.validate().is_ok());
+
+		config.session.ttl = 0;
+		assert!(config.validate().is_err());
+		config.session.ttl = 1;
+
+		config.admin_token = Some("short".to_string());
+		assert!(config.validate().is_err());
+		config.admin_token = Some("0123456789abcdef".to_string());
+		assert!(config.validate().is_ok());
+
+		config.log_filter = Some("server=debug,hyper=warn".to_string());
+		assert!(config.validate().is_ok());
+		config.log_filter = Some("server=loud".to_string());
+		assert!(config.validate().is_err());
+		config.log_filter = None;
+
+		config.static_dir.push("nowhere");
+		assert!(config.validate().is_err());
+	}
+}
@@ -0,0 +1,179 @@
+use hyper::{ Body, Method, Request, Response, StatusCode, Version };
+use hyper::header::{ HeaderMap, HeaderName, CONNECTION, UPGRADE, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION };
+use headers::HeaderMapExt;
+use crossplatform::proto_ws::{ subprotocol, SUBPROTOCOL_PREFIX };
+use crate::admission::protocols;
+use crate::Result;
+
+// Opening handshake checks from RFC 6455 section 4.2.1, the subprotocol
+// carry the WebSocketData version (p2p-chat.v<version>)
+
+const WEBSOCKET_VERSION: &str = "13";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+	Method, // not a GET
+	HttpVersion, // older than HTTP/1.1
+	Upgrade, // no `Upgrade: websocket`
+	Connection, // no `Connection: upgrade`
+	Key, // missing or not 16 base64 encoded bytes
+	Version, // Sec-WebSocket-Version is not 13
+	Protocol // only other versions of our protocol were offered
+}
+
+impl Rejected {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Rejected::Method => "method",
+			Rejected::HttpVersion => "http_version",
+			Rejected::Upgrade => "upgrade",
+			Rejected::Connection => "connection",
+			Rejected::Key => "key",
+			Rejected::Version => "version",
+			Rejected::Protocol => "protocol"
+		}
+	}
+
+	pub fn response(self) -> Result<Response<Body>> {
+		let rsp = Response::builder();
+		Ok(match self {
+			// tell the client which version we speak
+			Rejected::Version => rsp.status(StatusCode::UPGRADE_REQUIRED)
+				.header(SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION)
+				.body(Body::from("Unsupported websocket version"))?,
+			Rejected::Protocol => rsp.status(StatusCode::BAD_REQUEST)
+				.body(Body::from(format!("Unsupported protocol version, this server speaks {}", subprotocol())))?,
+			_ => rsp.status(StatusCode::BAD_REQUEST)
+				.body(Body::from(format!("Invalid websocket handshake: {}", self.as_str())))?
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct Handshake {
+	pub key: headers::SecWebsocketKey,
+	pub protocol: Option<String> // selected subprotocol
+}
+
+// Comma separated header containing `token`, case insensitive
+fn has_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+	headers.get_all(name).iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+// Asking for a websocket, valid or not
+pub fn is_upgrade(req: &Request<Body>) -> bool {
+	has_token(req.headers(), UPGRADE, "websocket")
+}
+
+pub fn validate(req: &Request<Body>) -> std::result::Result<Handshake, Rejected> {
+	if req.method() != Method::GET {
+		return Err(Rejected::Method);
+	}
+	if req.version() < Version::HTTP_11 {
+		return Err(Rejected::HttpVersion);
+	}
+	let headers = req.headers();
+	if !is_upgrade(req) {
+		return Err(Rejected::Upgrade);
+	}
+	if !has_token(headers, CONNECTION, "upgrade") {
+		return Err(Rejected::Connection);
+	}
+	let nonce = headers.get(SEC_WEBSOCKET_KEY).map(|key| base64::decode(key.as_bytes()));
+	let key = match (nonce, headers.typed_get::<headers::SecWebsocketKey>()) {
+		(Some(Ok(nonce)), Some(key)) if nonce.len() == 16 => key,
+		_ => return Err(Rejected::Key)
+	};
+	if headers.get_all(SEC_WEBSOCKET_VERSION).iter().count() != 1
+		|| headers.get(SEC_WEBSOCKET_VERSION).map(|v| v.as_bytes()) != Some(WEBSOCKET_VERSION.as_bytes()) {
+		return Err(Rejected::Version);
+	}
+	// clients without subprotocol are accepted, they predate the versioning
+	let ours = subprotocol();
+	let versions = protocols(req).filter(|protocol| protocol.starts_with(SUBPROTOCOL_PREFIX)).collect::<Vec<_>>();
+	let protocol = if versions.is_empty() {
+		None
+	} else if versions.contains(&ours.as_str()) {
+		Some(ours)
+	} else {
+		return Err(Rejected::Protocol);
+	};
+	Ok(Handshake { key, protocol })
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{ Body, Request, Method, Version, StatusCode };
+	use hyper::http::request::Builder;
+	use crossplatform::proto_ws::subprotocol;
+	use super::{ validate, Rejected };
+
+	fn valid() -> Builder {
+		Request::builder()
+			.uri("/")
+			.header("Host", "localhost")
+			.header("Upgrade", "websocket")
+			.header("Connection", "Upgrade")
+			.header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+			.header("Sec-WebSocket-Version", "13")
+	}
+
+	// valid() without one header
+	fn without(skip: &str) -> Builder {
+		let mut req = Request::builder();
+		for (name, value) in valid().headers_ref().unwrap().iter() {
+			if name != skip {
+				req = req.header(name, value);
+			}
+		}
+		req
+	}
+
+	fn check(builder: Builder) -> Result<Option<String>, Rejected> {
+		validate(&builder.body(Body::empty()).unwrap()).map(|handshake| handshake.protocol)
+	}
+
+	#[test]
+	fn accepted() {
+		assert_eq!(check(valid()), Ok(None));
+		// firefox sends `Connection: keep-alive, Upgrade`
+		let req = without("connection").header("Connection", "keep-alive, Upgrade");
+		assert_eq!(check(req), Ok(None));
+		let req = without("upgrade").header("Upgrade", "WebSocket");
+		assert_eq!(check(req), Ok(None));
+		let req = valid().header("Sec-WebSocket-Protocol", format!("p2p-chat.v0, {}", subprotocol()));
+		assert_eq!(check(req), Ok(Some(subprotocol())));
+		let req = valid().header("Sec-WebSocket-Protocol", "token.1234.abcd");
+		assert_eq!(check(req), Ok(None));
+	}
+
+	#[test]
+	fn malformed() {
+		assert_eq!(check(valid().method(Method::POST)), Err(Rejected::Method));
+		assert_eq!(check(valid().version(Version::HTTP_10)), Err(Rejected::HttpVersion));
+
+		assert_eq!(check(without("upgrade")), Err(Rejected::Upgrade));
+		assert_eq!(check(without("connection")), Err(Rejected::Connection));
+		assert_eq!(check(without("sec-websocket-key")), Err(Rejected::Key));
+		assert_eq!(check(without("sec-websocket-version")), Err(Rejected::Version));
+
+		assert_eq!(check(without("connection").header("Connection", "keep-alive")), Err(Rejected::Connection));
+		assert_eq!(check(without("sec-websocket-key").header("Sec-WebSocket-Key", "not base64")), Err(Rejected::Key));
+		assert_eq!(check(without("sec-websocket-key").header("Sec-WebSocket-Key", "c2hvcnQ=")), Err(Rejected::Key));
+		assert_eq!(check(without("sec-websocket-version").header("Sec-WebSocket-Version", "8")), Err(Rejected::Version));
+		assert_eq!(check(valid().header("Sec-WebSocket-Version", "8")), Err(Rejected::Version));
+		assert_eq!(check(valid().header("Sec-WebSocket-Protocol", "p2p-chat.v0")), Err(Rejected::Protocol));
+	}
+
+	#[test]
+	fn responses() {
+		let rsp = Rejected::Version.response().unwrap();
+		assert_eq!(rsp.status(), StatusCode::UPGRADE_REQUIRED);
+		assert_eq!(rsp.headers()["Sec-WebSocket-Version"], "13");
+		assert_eq!(Rejected::Key.response().unwrap().status(), StatusCode::BAD_REQUEST);
+		assert_eq!(Rejected::Protocol.response().unwrap().status(), StatusCode::BAD_REQUEST);
+	}
+}
@@ -0,0 +1,191 @@
+use std::{
+	collections::{ HashMap, HashSet },
+	net::{ IpAddr, SocketAddr },
+	sync::{Arc, Mutex}
+};
+use std::path::Path;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::SystemTime;
+use std::ffi::OsStr;
+use tokio::fs::File;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::server::conn::AddrStream;
+
+use futures::channel::mpsc::UnboundedSender;
+use std::future::Future;
+// use futures_util::stream::StreamExt;
+use tungstenite::protocol::Message;
+
+use crossplatform::id::Id;
+use tracing::{ trace, debug, error };
+
+mod websocket;
+mod process;
+pub mod config;
+mod turn;
+mod relay;
+mod metrics;
+mod admin;
+mod ratelimit;
+mod admission;
+mod handshake;
+pub mod shutdown;
+pub mod session;
+
+use config::Config;
+use relay::Relay;
+use metrics::Metrics;
+use ratelimit::RateLimiter;
+use session::Sessions;
+
+type Tx = UnboundedSender<Message>;
+type PeerMap = Arc<Mutex<HashMap<SocketAddr, Peer>>>;
+
+#[derive(Debug)]
+pub struct Peer {
+	pub id: Id,
+	pub tx: Tx,
+	pub connected_at: SystemTime,
+	pub last_activity: SystemTime,
+	pub offered: HashSet<SocketAddr>, // got an offer of this peer through us
+	pub brokered: HashSet<SocketAddr> // offer and answer went through us, the relay is allowed
+}
+
+impl Peer {
+	pub fn new(id: Id, tx: Tx) -> Self {
+		let now = SystemTime::now();
+		Peer { id, tx, connected_at: now, last_activity: now, offered: HashSet::new(), brokered: HashSet::new() }
+	}
+}
+
+// Shared by every connection
+pub struct State {
+	pub config: Config,
+	pub peers: PeerMap,
+	pub relay: Relay,
+	pub metrics: Metrics,
+	pub limiter: RateLimiter,
+	pub sessions: Sessions,
+	pub upgrading: Mutex<HashMap<IpAddr, usize>>, // slots taken by the upgrades not yet in peers
+	// route the OfferSDP without a target, can be switched by the admin api
+	pub matchmaking: AtomicBool,
+	pub shutting_down: AtomicBool
+}
+
+impl State {
+	pub fn new(config: Config) -> Self {
+		State {
+			peers: PeerMap::new(Mutex::new(HashMap::new())),
+			relay: Relay::new(&config.relay),
+			metrics: Metrics::default(),
+			limiter: RateLimiter::new(&config.rate_limits),
+			sessions: Sessions::new(&config.session),
+			upgrading: Mutex::new(HashMap::new()),
+			matchmaking: AtomicBool::new(true),
+			shutting_down: AtomicBool::new(false),
+			config
+		}
+	}
+
+	// Connected websockets
+	pub fn peers(&self) -> usize {
+		self.peers.lock().unwrap().len()
+	}
+}
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn log_err<T: core::fmt::Display>(arg: std::result::Result<(), T>) {
+	if let Err(e) = arg {
+		error!("Unhandled error: {}", e);
+	}
+}
+
+pub async fn send_static(config: &Config, req: Request<Body>) -> Result<Response<Body>> {
+	let uri = match &(req.uri().to_string())[..] {
+		"/" => "index.html".to_string(),
+		uri => uri.to_string()
+	};
+	let mime = match Path::new(&uri).extension().and_then(OsStr::to_str).unwrap() {
+		"html" => "text/html",
+		"js" => "application/javascript",
+        "wasm" => "application/wasm",
+        "css" => "text/css",
+		_ => {
+			debug!(%uri, "no mime type");
+			""
+		}
+	};
+	let static_folder = &config.static_dir;
+	// TODO: Range header
+	let file = match File::open(static_folder.join(uri.trim_start_matches('/'))).await {
+		Ok(file) => file,
+		Err(_) => File::open(static_folder.join("index.html")).await?
+	};
+	let stream = FramedRead::new(file, BytesCodec::new());
+	let body = Body::wrap_stream(stream);
+	let builder = Response::builder()
+		.header(hyper::header::CONTENT_TYPE, mime)
+		.status(StatusCode::OK);
+
+	Ok(builder.body(body)?)
+}
+
+fn text(status: StatusCode, content_type: &'static str, body: String) -> Result<Response<Body>> {
+	Ok(Response::builder()
+		.header(hyper::header::CONTENT_TYPE, content_type)
+		.status(status)
+		.body(Body::from(body))?)
+}
+
+// Operators endpoints
+fn monitoring(state: &State, req: &Request<Body>) -> Option<Result<Response<Body>>> {
+	Some(match req.uri().path() {
+		"/healthz" => text(StatusCode::OK, "text/plain", "ok\n".to_string()),
+		// Can we take more peers
+		"/readyz" => if state.shutting_down.load(Ordering::Relaxed) {
+			text(StatusCode::SERVICE_UNAVAILABLE, "text/plain", "shutting down\n".to_string())
+		} else if state.peers() < state.config.max_peers {
+			text(StatusCode::OK, "text/plain", "ready\n".to_string())
+		} else {
+			text(StatusCode::SERVICE_UNAVAILABLE, "text/plain", "full\n".to_string())
+		},
+		"/metrics" => text(StatusCode::OK, "text/plain; version=0.0.4", state.metrics.render(state.peers())),
+		_ => return None
+	})
+}
+
+/// Our server HTTP handler to initiate HTTP upgrades.
+async fn handler(state: Arc<State>, addr: SocketAddr, req: Request<Body>) -> Result<Response<Body>> {
+	trace!(%addr, method = %req.method(), uri = %req.uri(), headers = ?req.headers(), "incoming request");
+	let res = if handshake::is_upgrade(&req) {
+		websocket::handler(state, addr, req).await
+	} else if req.uri().path().starts_with("/admin/") {
+		admin::handler(state, req).await
+	} else if let Some(res) = monitoring(&state, &req) {
+		res
+	} else { send_static(&state.config, req).await };
+	if let Ok(res) = &res {
+		trace!(%addr, status = %res.status(), headers = ?res.headers(), "outgoing response");
+	}
+	res
+}
+
+// Serve `addr` until `signal`, with the address actually bound (port 0 picks a free one)
+pub fn listen(state: Arc<State>, addr: &SocketAddr, signal: impl Future<Output = ()>)
+	-> Result<(SocketAddr, impl Future<Output = hyper::Result<()>>)> {
+	let new_service = make_service_fn(move |conn: &AddrStream| {
+			let addr = conn.remote_addr();
+			let state = state.clone();
+			async move {
+				Ok::<_, hyper::Error>(service_fn(move |req| handler(state.clone(), addr, req)))
+			}
+		});
+
+	let server = Server::try_bind(addr)
+		.map_err(|e| format!("Cannot listen on {}: {}", addr, e))?
+		.serve(new_service);
+	Ok((server.local_addr(), server.with_graceful_shutdown(signal)))
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use crossplatform::proto_ws::WebSocketData;
+
+// Prometheus text format, rendered by hand on /metrics
+
+// Counter with a single label
+#[derive(Debug, Default)]
+struct Family(Mutex<BTreeMap<&'static str, u64>>);
+
+impl Family {
+	fn inc(&self, label: &'static str) {
+		*self.0.lock().unwrap().entry(label).or_insert(0) += 1;
+	}
+
+	fn render(&self, out: &mut String, name: &str, label: &str) {
+		for (value, count) in self.0.lock().unwrap().iter() {
+			writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count).unwrap();
+		}
+	}
+}
+
+// Where an OfferSDP ended up
+#[derive(Debug, Clone, Copy)]
+pub enum Matchmaking {
+	Closest, // routed to the closest peer
+	Direct, // the client asked for a peer
+	Alone, // nobody else on the server
+	NotFound, // asked peer is gone, or no closest one
+	Disabled // switched off by the admin api
+}
+
+impl Matchmaking {
+	fn as_str(self) -> &'static str {
+		match self {
+			Matchmaking::Closest => "closest",
+			Matchmaking::Direct => "direct",
+			Matchmaking::Alone => "alone",
+			Matchmaking::NotFound => "not_found",
+			Matchmaking::Disabled => "disabled"
+		}
+	}
+}
+
+pub fn variant(msg: &WebSocketData) -> &'static str {
+	match msg {
+		WebSocketData::OfferSDP(..) => "OfferSDP",
+		WebSocketData::AnswerSDP(..) => "AnswerSDP",
+		WebSocketData::IceCandidate(..) => "IceCandidate",
+		WebSocketData::Message(..) => "Message",
+		WebSocketData::Id(..) => "Id",
+		WebSocketData::IceServers(..) => "IceServers",
+		WebSocketData::TurnCredentials(..) => "TurnCredentials",
+		WebSocketData::Relay(..) => "Relay",
+		WebSocketData::RelayClosed(..) => "RelayClosed",
+		WebSocketData::Notice(..) => "Notice",
+		WebSocketData::GoingAway(..) => "GoingAway",
+		WebSocketData::Resume(..) => "Resume",
+		WebSocketData::Session(..) => "Session",
+		WebSocketData::Neighbourhood(..) => "Neighbourhood"
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+	messages: Family,
+	matchmaking: Family,
+	upgrade_failures: Family,
+	rate_limited: Family,
+	broadcasts: AtomicU64,
+	broadcast_recipients: AtomicU64,
+	dropped_frames: AtomicU64
+}
+
+impl Metrics {
+	// Message received from a client
+	pub fn message(&self, msg: &WebSocketData) {
+		self.messages.inc(variant(msg));
+	}
+
+	pub fn matchmaking(&self, outcome: Matchmaking) {
+		self.matchmaking.inc(outcome.as_str());
+	}
+
+	pub fn upgrade_failure(&self, reason: &'static str) {
+		self.upgrade_failures.inc(reason);
+	}
+
+	// Frame over the rate limit, the connection is closed
+	pub fn rate_limited(&self, msg: &WebSocketData) {
+		self.rate_limited.inc(variant(msg));
+	}
+
+	pub fn broadcast(&self, recipients: usize) {
+		self.broadcasts.fetch_add(1, Ordering::Relaxed);
+		self.broadcast_recipients.fetch_add(recipients as u64, Ordering::Relaxed);
+	}
+
+	// Frame that could not be parsed, serialized or queued
+	pub fn dropped_frame(&self) {
+		self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn render(&self, peers: usize) -> String {
+		let mut out = String::new();
+		out.push_str("# HELP p2p_peers Connected websocket clients.\n");
+		out.push_str("# TYPE p2p_peers gauge\n");
+		writeln!(out, "p2p_peers {}", peers).unwrap();
+		out.push_str("# HELP p2p_messages_total Signaling messages received, by WebSocketData variant.\n");
+		out.push_str("# TYPE p2p_messages_total counter\n");
+		self.messages.render(&mut out, "p2p_messages_total", "variant");
+		out.push_str("# HELP p2p_matchmaking_total OfferSDP routing outcomes.\n");
+		out.push_str("# TYPE p2p_matchmaking_total counter\n");
+		self.matchmaking.render(&mut out, "p2p_matchmaking_total", "outcome");
+		out.push_str("# HELP p2p_broadcasts_total Broadcasted messages.\n");
+		out.push_str("# TYPE p2p_broadcasts_total counter\n");
+		writeln!(out, "p2p_broadcasts_total {}", self.broadcasts.load(Ordering::Relaxed)).unwrap();
+		out.push_str("# HELP p2p_broadcast_recipients_total Frames sent by the broadcasts.\n");
+		out.push_str("# TYPE p2p_broadcast_recipients_total counter\n");
+		writeln!(out, "p2p_broadcast_recipients_total {}", self.broadcast_recipients.load(Ordering::Relaxed)).unwrap();
+		out.push_str("# HELP p2p_dropped_frames_total Frames that could not be parsed, serialized or delivered.\n");
+		out.push_str("# TYPE p2p_dropped_frames_total counter\n");
+		writeln!(out, "p2p_dropped_frames_total {}", self.dropped_frames.load(Ordering::Relaxed)).unwrap();
+		out.push_str("# HELP p2p_upgrade_failures_total Websocket upgrades that did not succeed, by reason.\n");
+		out.push_str("# TYPE p2p_upgrade_failures_total counter\n");
+		self.upgrade_failures.render(&mut out, "p2p_upgrade_failures_total", "reason");
+		out.push_str("# HELP p2p_rate_limited_total Connections closed for sending too many frames, by WebSocketData variant.\n");
+		out.push_str("# TYPE p2p_rate_limited_total counter\n");
+		self.rate_limited.render(&mut out, "p2p_rate_limited_total", "variant");
+		out
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crossplatform::proto_ws::WebSocketData;
+	use super::{ Metrics, Matchmaking };
+
+	#[test]
+	fn render() {
+		let metrics = Metrics::default();
+		metrics.message(&WebSocketData::Id(None));
+		metrics.message(&WebSocketData::Id(None));
+		metrics.message(&WebSocketData::Message("hello".to_string()));
+		metrics.matchmaking(Matchmaking::Closest);
+		metrics.broadcast(3);
+		metrics.broadcast(2);
+		metrics.dropped_frame();
+		metrics.upgrade_failure("timeout");
+		metrics.rate_limited(&WebSocketData::Message("spam".to_string()));
+
+		let out = metrics.render(4);
+		assert!(out.contains("p2p_peers 4\n"));
+		assert!(out.contains("p2p_messages_total{variant=\"Id\"} 2\n"));
+		assert!(out.contains("p2p_messages_total{variant=\"Message\"} 1\n"));
+		assert!(out.contains("p2p_matchmaking_total{outcome=\"closest\"} 1\n"));
+		assert!(out.contains("p2p_broadcasts_total 2\n"));
+		assert!(out.contains("p2p_broadcast_recipients_total 5\n"));
+		assert!(out.contains("p2p_dropped_frames_total 1\n"));
+		assert!(out.contains("p2p_upgrade_failures_total{reason=\"timeout\"} 1\n"));
+		assert!(out.contains("p2p_rate_limited_total{variant=\"Message\"} 1\n"));
+	}
+}
@@ -1,12 +1,21 @@
 // use protocols::WebSocketData;
 use crossplatform::proto_ws::WebSocketData;
+use crossplatform::topology::{ Topology, Neighbourhood };
 use std::net::SocketAddr;
 use std::collections::HashMap;
+use std::time::SystemTime;
+use std::sync::atomic::Ordering;
+use std::borrow::Cow;
 use tungstenite::Message;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::protocol::frame::coding::CloseCode;
 use futures::channel::mpsc::UnboundedSender;
-use crate::PeerMap;
-use crate::Id;
-use crate::log_err;
+use crate::{ PeerMap, Peer };
+use crate::config::Config;
+use crate::turn;
+use crate::metrics::{ Metrics, Matchmaking };
+use crate::State;
+use tracing::{ trace, warn, error, Span };
 
 /* WebSocketData to Message
 let rsp = match rsp.into_u8() {
@@ -19,98 +28,205 @@ let rsp = match rsp.into_u8() {
 */
 
 type Tx = UnboundedSender<Message>;
-type PeerMapLock = HashMap<SocketAddr, (Id, Tx)>;
+type PeerMapLock = HashMap<SocketAddr, Peer>;
 
-fn broadcast_msg(msg: WebSocketData, addr: SocketAddr, peers: &PeerMap) -> Option<WebSocketData> {
-	let peers = peers.lock().unwrap();
+// Queue a message for a peer, count it as dropped if it cannot
+pub fn send_to(psender: &Tx, msg: &WebSocketData, metrics: &Metrics) {
+	match msg.into_u8() {
+		Ok(rsp) => {
+			if let Err(e) = psender.unbounded_send(Message::binary(rsp)) {
+				metrics.dropped_frame();
+				warn!("Cannot queue the message: {}", e);
+			}
+		},
+		Err(e) => {
+			metrics.dropped_frame();
+			error!("Error while creating data from msg: {}", e);
+		}
+	};
+}
+
+// Close frame, the connection end once it is sent
+pub fn close(psender: &Tx, code: CloseCode, reason: &'static str, metrics: &Metrics) {
+	let frame = CloseFrame { code, reason: Cow::Borrowed(reason) };
+	if psender.unbounded_send(Message::Close(Some(frame))).is_err() {
+		metrics.dropped_frame();
+	}
+}
+
+fn broadcast_msg(msg: WebSocketData, addr: SocketAddr, state: &State) -> Option<WebSocketData> {
+	let peers = state.peers.lock().unwrap();
 	let broadcast_recipients = peers
 		.iter()
 		.filter(|(peer_addr, _)| peer_addr != &&addr)
-		.map(|(_, (_id, ws_sink))| ws_sink);
+		.map(|(_, peer)| &peer.tx);
 	
 	match msg.into_u8() {
 		Ok(resp) => {
 			let resp = Message::Binary(resp);
+			let mut count = 0;
 			for recp in broadcast_recipients {
-				log_err(recp.unbounded_send(resp.clone()));
+				if recp.unbounded_send(resp.clone()).is_err() {
+					state.metrics.dropped_frame();
+				}
+				count += 1;
 			};
+			state.metrics.broadcast(count);
+		}
+		Err(e) => {
+			state.metrics.dropped_frame();
+			error!("error while !parsing message {}", e)
 		}
-		Err(e) => eprintln!("error while !parsing message {}", e)
 	};
 	None
 }
 
-fn closest_peer<'a>(addr: SocketAddr, peers: &'a PeerMapLock) -> Option<&'a UnboundedSender<Message>> {
-	let (id, _) = peers.get(&addr)?;
-
-	println!("Peers: {:?}", peers);
-	let mut distance = u64::MAX;
-	let mut res = None;
+fn closest_peer(addr: SocketAddr, peers: &PeerMapLock, topology: Neighbourhood) -> Option<SocketAddr> {
+	let id = peers.get(&addr)?.id;
 
-	for (_, (i_id, i_sender)) in peers.iter() {
-		if id == i_id {
-			continue;
-		}
-		let i_distance = id.distance(i_id);
-		if i_distance < distance {
-			distance = i_distance;
-			res = Some(i_sender);
-		}
-	}
-	res
+	trace!(peers = peers.len(), "looking for the closest peer");
+	topology.closest(id, peers.iter().map(|(paddr, peer)| (peer.id, *paddr)))
 }
 
-fn offer_sdp(addr: SocketAddr, paddr: Option<SocketAddr>, data: String, peers: &PeerMap) -> Option<WebSocketData> {
-	let peers = peers.lock().unwrap();
+fn offer_sdp(addr: SocketAddr, paddr: Option<SocketAddr>, data: String, state: &State) -> Option<WebSocketData> {
+	let mut peers = state.peers.lock().unwrap();
 
 	let len = peers.len();
-	if len < 2 { return None };
-
-	let psender = match paddr {
-		Some(paddr) => &peers.get(&paddr)?.1,
-		None => closest_peer(addr, &*peers)?
+	if len < 2 {
+		state.metrics.matchmaking(Matchmaking::Alone);
+		return None
 	};
 
-	println!("got a psender");
-	let rsp = WebSocketData::OfferSDP(data, Some(addr));
-	match rsp.into_u8() {
-		Ok(rsp) => log_err(psender.unbounded_send(Message::binary(rsp))),
-		Err(e) => eprintln!("Error while creating data from msg: {}", e)
+	let paddr = match paddr {
+		Some(paddr) if peers.contains_key(&paddr) => {
+			state.metrics.matchmaking(Matchmaking::Direct);
+			paddr
+		},
+		Some(_) => {
+			state.metrics.matchmaking(Matchmaking::NotFound);
+			return None;
+		},
+		None if !state.matchmaking.load(Ordering::Relaxed) => {
+			state.metrics.matchmaking(Matchmaking::Disabled);
+			return None;
+		},
+		None => match closest_peer(addr, &peers, state.config.neighbourhood) {
+			Some(paddr) => {
+				state.metrics.matchmaking(Matchmaking::Closest);
+				paddr
+			},
+			None => {
+				state.metrics.matchmaking(Matchmaking::NotFound);
+				return None;
+			}
+		}
 	};
+
+	let peer = peers.get_mut(&paddr)?;
+	peer.offered.insert(addr);
+	send_to(&peer.tx, &WebSocketData::OfferSDP(data, Some(addr)), &state.metrics);
 	None
 }
 
+// The answer to an offer we brokered opens the relay between them
+fn answer_sdp(addr: SocketAddr, paddr: SocketAddr, data: String, state: &State) -> Option<WebSocketData> {
+	{
+		let mut peers = state.peers.lock().unwrap();
+		if peers.get_mut(&addr)?.offered.remove(&paddr) {
+			peers.get_mut(&addr)?.brokered.insert(paddr);
+			if let Some(peer) = peers.get_mut(&paddr) {
+				peer.brokered.insert(addr);
+			}
+		}
+	}
+	proxy(paddr, WebSocketData::AnswerSDP(data, addr), state)
+}
+
 // function for both answerSDP and IceCandidate proxiing
-fn proxy(paddr: SocketAddr, msg: WebSocketData, peers: &PeerMap) -> Option<WebSocketData> {
-	let peers = peers.lock().unwrap();
-	let (_id, psender) = peers.get(&paddr)?;
+fn proxy(paddr: SocketAddr, msg: WebSocketData, state: &State) -> Option<WebSocketData> {
+	let peers = state.peers.lock().unwrap();
+	let psender = &peers.get(&paddr)?.tx;
 
-	match msg.into_u8() {
-		Ok(rsp) => log_err(psender.unbounded_send(Message::Binary(rsp))),
-		Err(e) => eprintln!("Error while creating data from msg: {}", e)
-	};
+	send_to(psender, &msg, &state.metrics);
 	None
 }
 
 fn send_id(addr: SocketAddr, peers: &PeerMap) -> Option<WebSocketData> {
 	let peers = peers.lock().unwrap();
-	let (id, _sender) = peers.get(&addr)?;
-	Some(WebSocketData::Id(Some(id.clone())))
+	let id = peers.get(&addr)?.id;
+	Some(WebSocketData::Id(Some(id)))
 }
 
-fn set_id(addr: SocketAddr, peers: &PeerMap, id: Id) -> Option<WebSocketData> {
-	let mut peers = peers.lock().unwrap();
-	peers.entry(addr).and_modify(|e| (*e).0 = id);
+// Restore the Id of a valid token, the connection it was used by (gone
+// but maybe not timed out yet) is closed
+fn resume(addr: SocketAddr, token: Option<String>, state: &State) -> Option<WebSocketData> {
+	let now = SystemTime::now();
+	let mut peers = state.peers.lock().unwrap();
+	let resumed = token.and_then(|token| state.sessions.resume(&token, now));
+	let id = match resumed {
+		Some(id) => {
+			let previous = peers.iter()
+				.find(|(paddr, peer)| peer.id == id && **paddr != addr)
+				.map(|(paddr, _)| *paddr);
+			if let Some(previous) = previous.and_then(|paddr| peers.remove(&paddr)) {
+				close(&previous.tx, CloseCode::Policy, "Session resumed by another connection", &state.metrics);
+			}
+			peers.get_mut(&addr)?.id = id;
+			Span::current().record("id", id.to_name().as_str());
+			id
+		},
+		None => peers.get(&addr)?.id
+	};
+	Some(WebSocketData::Session(id, state.sessions.token(id, now)))
+}
+
+fn turn_credentials(config: &Config, addr: SocketAddr, peers: &PeerMap) -> Option<WebSocketData> {
+	let turn = config.turn.as_ref()?;
+	let id = peers.lock().unwrap().get(&addr)?.id;
+	let creds = turn::credentials(turn, &id.to_name(), SystemTime::now());
+	Some(WebSocketData::TurnCredentials(Some(creds)))
+}
+
+// Forward to paddr, between the peers we brokered only, the sender pay for the bytes
+fn relay(addr: SocketAddr, paddr: SocketAddr, data: Vec<u8>, state: &State) -> Option<WebSocketData> {
+	let peers = state.peers.lock().unwrap();
+	if !peers.get(&addr)?.brokered.contains(&paddr) {
+		warn!(%paddr, "relay to a peer we did not broker");
+		return Some(WebSocketData::RelayClosed(paddr));
+	}
+	if !state.relay.consume(addr, data.len()) {
+		return Some(WebSocketData::RelayClosed(paddr));
+	}
+	let psender = match peers.get(&paddr) {
+		Some(peer) => &peer.tx,
+		None => return Some(WebSocketData::RelayClosed(paddr))
+	};
+	send_to(psender, &WebSocketData::Relay(data, addr), &state.metrics);
 	None
 }
 
-pub fn process(addr: SocketAddr, msg: WebSocketData, peers: &PeerMap) -> Option<WebSocketData> {
+pub fn process(state: &State, addr: SocketAddr, msg: WebSocketData) -> Option<WebSocketData> {
+	let (config, peers) = (&state.config, &state.peers);
+	state.metrics.message(&msg);
 	match msg {
-		WebSocketData::OfferSDP(data, paddr) => offer_sdp(addr , paddr, data, peers),
-		WebSocketData::AnswerSDP(data, paddr) => proxy(paddr, WebSocketData::AnswerSDP(data, addr), peers),
-		WebSocketData::IceCandidate(data, paddr) => proxy(paddr, WebSocketData::IceCandidate(data, addr), peers),
-		WebSocketData::Message(_) =>  broadcast_msg(msg, addr, peers),
-		WebSocketData::Id(Some(id)) => set_id(addr, peers, id),
-		WebSocketData::Id(None) => send_id(addr, peers)
+		WebSocketData::OfferSDP(data, paddr) => offer_sdp(addr , paddr, data, state),
+		WebSocketData::AnswerSDP(data, paddr) => answer_sdp(addr, paddr, data, state),
+		WebSocketData::IceCandidate(data, paddr) => proxy(paddr, WebSocketData::IceCandidate(data, addr), state),
+		WebSocketData::Message(_) =>  broadcast_msg(msg, addr, state),
+		WebSocketData::Id(Some(_)) => {
+			warn!("a client cannot choose its id, it needs a session token");
+			None
+		},
+		WebSocketData::Id(None) => send_id(addr, peers),
+		WebSocketData::Resume(token) => resume(addr, token, state),
+		WebSocketData::TurnCredentials(None) => turn_credentials(config, addr, peers),
+		WebSocketData::Relay(data, paddr) => relay(addr, paddr, data, state),
+		WebSocketData::IceServers(_)
+		| WebSocketData::TurnCredentials(Some(_))
+		| WebSocketData::RelayClosed(_)
+		| WebSocketData::Notice(_)
+		| WebSocketData::GoingAway(_)
+		| WebSocketData::Session(..)
+		| WebSocketData::Neighbourhood(_) => None // server -> client only
 	}
-}
\ No newline at end of file
+}
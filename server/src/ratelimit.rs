@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use crossplatform::proto_ws::WebSocketData;
+use crate::config::{ Limit, RateLimits };
+
+// Token buckets of each connection, a connection going over does not close the
+// others from the same ip (NAT), max_peers_per_ip bounds what an ip can send
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+	Offer,
+	Message,
+	Other
+}
+
+impl Kind {
+	fn of(msg: &WebSocketData) -> Self {
+		match msg {
+			WebSocketData::OfferSDP(..) => Kind::Offer,
+			WebSocketData::Message(..) => Kind::Message,
+			_ => Kind::Other
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	last: Instant
+}
+
+impl Bucket {
+	fn new(limit: &Limit, now: Instant) -> Self {
+		Bucket { tokens: limit.burst as f64, last: now }
+	}
+
+	fn take(&mut self, limit: &Limit, now: Instant) -> bool {
+		let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+		self.last = now;
+		if self.tokens < 1.0 {
+			return false;
+		}
+		self.tokens -= 1.0;
+		true
+	}
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+	limits: RateLimits,
+	buckets: Mutex<HashMap<(SocketAddr, Kind), Bucket>>
+}
+
+impl RateLimiter {
+	pub fn new(limits: &RateLimits) -> Self {
+		RateLimiter {
+			limits: *limits,
+			buckets: Mutex::new(HashMap::new())
+		}
+	}
+
+	fn limit(&self, kind: Kind) -> &Limit {
+		match kind {
+			Kind::Offer => &self.limits.offer,
+			Kind::Message => &self.limits.message,
+			Kind::Other => &self.limits.other
+		}
+	}
+
+	// false if the connection `addr` sent too many frames of this kind
+	pub fn allow(&self, addr: SocketAddr, msg: &WebSocketData, now: Instant) -> bool {
+		let kind = Kind::of(msg);
+		let limit = self.limit(kind);
+		let mut buckets = self.buckets.lock().unwrap();
+		buckets.entry((addr, kind))
+			.or_insert_with(|| Bucket::new(limit, now))
+			.take(limit, now)
+	}
+
+	// Once the connection `addr` is gone
+	pub fn remove(&self, addr: SocketAddr) {
+		self.buckets.lock().unwrap().retain(|(bucket_addr, _), _| *bucket_addr != addr);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, Instant };
+	use crossplatform::proto_ws::WebSocketData;
+	use crate::config::{ Limit, RateLimits };
+	use super::RateLimiter;
+
+	#[test]
+	fn buckets() {
+		let limits = RateLimits {
+			offer: Limit { rate: 1.0, burst: 2 },
+			message: Limit { rate: 10.0, burst: 1 },
+			other: Limit { rate: 1.0, burst: 1 }
+		};
+		let limiter = RateLimiter::new(&limits);
+		// two connections behind the same ip
+		let (ip1, ip2) = ("127.0.0.1:1000".parse().unwrap(), "127.0.0.1:1001".parse().unwrap());
+		let offer = WebSocketData::OfferSDP(String::new(), None);
+		let message = WebSocketData::Message(String::new());
+		let now = Instant::now();
+
+		assert!(limiter.allow(ip1, &offer, now));
+		assert!(limiter.allow(ip1, &offer, now));
+		assert!(!limiter.allow(ip1, &offer, now));
+		// every kind and connection has its own bucket
+		assert!(limiter.allow(ip1, &message, now));
+		assert!(!limiter.allow(ip1, &message, now));
+		assert!(limiter.allow(ip2, &offer, now));
+		// refilled with time, up to the burst
+		assert!(limiter.allow(ip1, &message, now + Duration::from_millis(100)));
+		assert!(limiter.allow(ip1, &offer, now + Duration::from_secs(1)));
+		assert!(!limiter.allow(ip1, &offer, now + Duration::from_secs(1)));
+		assert!(limiter.allow(ip1, &offer, now + Duration::from_secs(60)));
+		assert!(limiter.allow(ip1, &offer, now + Duration::from_secs(60)));
+		assert!(!limiter.allow(ip1, &offer, now + Duration::from_secs(60)));
+
+		limiter.remove(ip1);
+		assert!(limiter.allow(ip1, &offer, now + Duration::from_secs(60)));
+	}
+}
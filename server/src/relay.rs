@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use crate::config::RelayConfig;
+
+// Forward data between two clients when WebRTC cannot connect them,
+// every client can send up to `quota` bytes through the server
+#[derive(Debug)]
+pub struct Relay {
+	enabled: AtomicBool, // can be switched by the admin api
+	quota: u64,
+	used: Mutex<HashMap<SocketAddr, u64>>
+}
+
+impl Relay {
+	pub fn new(config: &RelayConfig) -> Self {
+		Relay {
+			enabled: AtomicBool::new(config.enabled),
+			quota: config.quota,
+			used: Mutex::new(HashMap::new())
+		}
+	}
+
+	// Account `len` bytes sent by `addr`, false if it goes over the quota
+	pub fn consume(&self, addr: SocketAddr, len: usize) -> bool {
+		if !self.enabled.load(Ordering::Relaxed) {
+			return false;
+		}
+		let mut used = self.used.lock().unwrap();
+		let used = used.entry(addr).or_insert(0);
+		let total = *used + len as u64;
+		if total > self.quota {
+			return false;
+		}
+		*used = total;
+		true
+	}
+
+	pub fn enabled(&self) -> &AtomicBool {
+		&self.enabled
+	}
+
+	pub fn remove(&self, addr: &SocketAddr) {
+		self.used.lock().unwrap().remove(addr);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::Ordering;
+	use crate::config::RelayConfig;
+	use super::Relay;
+
+	#[test]
+	fn quota() {
+		let addr1 = "127.0.0.1:1".parse().unwrap();
+		let addr2 = "127.0.0.1:2".parse().unwrap();
+		let relay = Relay::new(&RelayConfig { enabled: true, quota: 10 });
+		assert!(relay.consume(addr1, 6));
+		assert!(!relay.consume(addr1, 6));
+		assert!(relay.consume(addr1, 4));
+		assert!(!relay.consume(addr1, 1));
+		assert!(relay.consume(addr2, 10));
+		relay.remove(&addr1);
+		assert!(relay.consume(addr1, 10));
+
+		let relay = Relay::new(&RelayConfig { enabled: false, quota: 10 });
+		assert!(!relay.consume(addr1, 1));
+		relay.enabled().store(true, Ordering::Relaxed);
+		assert!(relay.consume(addr1, 1));
+	}
+}
@@ -0,0 +1,73 @@
+use std::time::SystemTime;
+use crossplatform::id::Id;
+use crate::admission::{ timestamp, sign, signed };
+use crate::config::SessionConfig;
+
+// Resume tokens, a client reconnecting with one get its old Id back
+// token = "<id>.<expiry unix timestamp>.<base64url(hmac-sha1(secret, "<id>.<expiry>"))>"
+
+#[derive(Debug)]
+pub struct Sessions {
+	secret: Vec<u8>,
+	ttl: u64
+}
+
+impl Sessions {
+	// Without a configured secret the tokens do not survive a restart
+	pub fn new(config: &SessionConfig) -> Self {
+		let secret = match &config.secret {
+			Some(secret) => secret.as_bytes().to_vec(),
+			None => (0..32).map(|_| rand::random::<u8>()).collect()
+		};
+		Sessions { secret, ttl: config.ttl }
+	}
+
+	pub fn token(&self, id: Id, now: SystemTime) -> String {
+		let payload = format!("{}.{}", id.0, timestamp(now) + self.ttl);
+		let signature = sign(&self.secret, &payload);
+		format!("{}.{}", payload, signature)
+	}
+
+	pub fn resume(&self, token: &str, now: SystemTime) -> Option<Id> {
+		let split = token.rfind('.')?;
+		let (payload, signature) = (&token[..split], &token[split + 1..]);
+		let mut parts = payload.splitn(2, '.');
+		let id = parts.next()?.parse::<u64>().ok()?;
+		let expiry = parts.next()?.parse::<u64>().ok()?;
+		if expiry < timestamp(now) || !signed(&self.secret, payload, signature) {
+			return None;
+		}
+		Some(Id(id))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, UNIX_EPOCH };
+	use crossplatform::id::Id;
+	use crate::config::SessionConfig;
+	use super::Sessions;
+
+	#[test]
+	fn resume() {
+		let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+		let config = SessionConfig { secret: Some("north".to_string()), ttl: 60 };
+		let sessions = Sessions::new(&config);
+		let id = Id::new(-12, 42);
+		let token = sessions.token(id, now);
+		assert_eq!(sessions.resume(&token, now), Some(id));
+		assert_eq!(sessions.resume(&token, now + Duration::from_secs(60)), Some(id));
+		assert_eq!(sessions.resume(&token, now + Duration::from_secs(61)), None);
+		// same secret after a restart
+		assert_eq!(Sessions::new(&config).resume(&token, now), Some(id));
+
+		let forged = token.replacen(&id.0.to_string(), &(id.0 + 1).to_string(), 1);
+		assert_eq!(sessions.resume(&forged, now), None);
+		assert_eq!(sessions.resume("", now), None);
+		assert_eq!(sessions.resume("1.2", now), None);
+
+		let random = Sessions::new(&SessionConfig { secret: None, ttl: 60 });
+		assert_eq!(random.resume(&token, now), None);
+		assert_eq!(random.resume(&random.token(id, now), now), Some(id));
+	}
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use futures::{ future, pin_mut };
+use tokio::time::{ delay_for, timeout };
+use tungstenite::protocol::frame::coding::CloseCode;
+use crossplatform::proto_ws::WebSocketData;
+use tracing::{ info, warn };
+use crate::process::{ send_to, close };
+use crate::State;
+
+// SIGINT or SIGTERM
+pub async fn signal() {
+	let ctrl_c = async {
+		tokio::signal::ctrl_c().await.expect("failed to install CTRL+C signal handler")
+	};
+	#[cfg(unix)]
+	let terminate = async {
+		use tokio::signal::unix::{ signal, SignalKind };
+		signal(SignalKind::terminate()).expect("failed to install SIGTERM signal handler").recv().await;
+	};
+	#[cfg(not(unix))]
+	let terminate = future::pending::<()>();
+	pin_mut!(ctrl_c, terminate);
+	future::select(ctrl_c, terminate).await;
+	info!("shutting down");
+}
+
+// Tell every client to come back later then wait for the websockets
+// to close, at most `timeouts.drain`
+pub async fn drain(state: &State) {
+	state.shutting_down.store(true, Ordering::Relaxed);
+	{
+		let peers = state.peers.lock().unwrap();
+		info!(peers = peers.len(), "closing the websockets");
+		// the clients pick a random time in this window to reconnect
+		let msg = WebSocketData::GoingAway(state.config.timeouts.reconnect);
+		for peer in peers.values() {
+			send_to(&peer.tx, &msg, &state.metrics);
+			close(&peer.tx, CloseCode::Away, "Server shutting down", &state.metrics);
+		}
+	}
+	// every connection remove itself from the map once closed
+	let closed = async {
+		while !state.peers.lock().unwrap().is_empty() {
+			delay_for(Duration::from_millis(100)).await;
+		}
+	};
+	if timeout(state.config.timeouts.drain(), closed).await.is_err() {
+		let peers = state.peers.lock().unwrap().len();
+		warn!(peers, "drain period over, dropping the remaining connections");
+	}
+}
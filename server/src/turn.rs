@@ -0,0 +1,47 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+use hmac::{ Hmac, Mac, NewMac };
+use sha1::Sha1;
+use crossplatform::proto_ws::{ IceServer, TurnCredentials };
+use crate::config::TurnConfig;
+
+// TURN REST api (draft-uberti-behave-turn-rest-00), also coturn `use-auth-secret`:
+// username = "<expiry unix timestamp>:<user>"
+// credential = base64(hmac-sha1(shared secret, username))
+
+pub fn credentials(turn: &TurnConfig, user: &str, now: SystemTime) -> TurnCredentials {
+	let now = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+	let username = format!("{}:{}", now + turn.ttl, user);
+	// hmac accept keys of any size
+	let mut mac = Hmac::<Sha1>::new_varkey(turn.secret.as_bytes()).unwrap();
+	mac.update(username.as_bytes());
+	let credential = base64::encode(mac.finalize().into_bytes());
+	TurnCredentials {
+		server: IceServer {
+			urls: turn.urls.clone(),
+			username: Some(username),
+			credential: Some(credential)
+		},
+		ttl: turn.ttl
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, UNIX_EPOCH };
+	use crate::config::TurnConfig;
+	use super::credentials;
+
+	#[test]
+	fn rest_api() {
+		let turn = TurnConfig {
+			urls: vec!("turn:turn.example.com:3478".to_string()),
+			secret: "north".to_string(),
+			ttl: 3600
+		};
+		let creds = credentials(&turn, "test", UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+		assert_eq!(creds.ttl, 3600);
+		assert_eq!(creds.server.urls, turn.urls);
+		assert_eq!(creds.server.username, Some("1600003600:test".to_string()));
+		assert_eq!(creds.server.credential, Some("UEtRdSkX/62X2Df9PvdtWLRm4Bo=".to_string()));
+	}
+}
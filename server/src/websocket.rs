@@ -3,104 +3,280 @@ use futures_util::{
 	stream::TryStreamExt,
 	StreamExt,
 };
-use std::net::SocketAddr;
+use std::borrow::Cow;
+use std::net::{ IpAddr, SocketAddr };
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{ Duration, Instant, SystemTime };
 use futures::channel::mpsc::unbounded;
 use hyper::upgrade::Upgraded;
+use tokio::time::timeout;
 use hyper::{Body, Request, Response, StatusCode};
 use headers::HeaderMapExt;
 use crossplatform::proto_ws::WebSocketData;
+use crossplatform::codec::MAX_FRAME_SIZE;
 use crossplatform::id::Id;
 use tungstenite::Message;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::protocol::frame::coding::CloseCode;
 use tungstenite::error::Error;
 use crate::process::process;
+use crate::turn;
 
-use crate::PeerMap;
+use crate::{ State, Peer };
 use crate::Result;
 use crate::log_err;
+use crate::process::{ send_to, close };
+use crate::metrics;
+use crate::admission;
+use crate::handshake;
+use tracing::{ debug, info, warn, error, info_span, field, Span, Instrument };
 
-async fn upgrade(peers: PeerMap, addr: SocketAddr, upgraded: Upgraded) {
-	// transform hyper upgraded to tungstenit stream
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1); // to send our close frame
+
+// A place among the peers taken before the 101 so the limits hold while the
+// upgrade completes, given back once dropped
+struct Slot {
+	state: Arc<State>,
+	ip: IpAddr
+}
+
+impl Drop for Slot {
+	fn drop(&mut self) {
+		let mut upgrading = self.state.upgrading.lock().unwrap();
+		if let Some(count) = upgrading.get_mut(&self.ip) {
+			*count -= 1;
+			if *count == 0 {
+				upgrading.remove(&self.ip);
+			}
+		}
+	}
+}
+
+async fn upgrade(state: Arc<State>, addr: SocketAddr, upgraded: Upgraded, slot: Slot) {
+	let config = &state.config;
+	let peers = &state.peers;
+	// transform hyper upgraded to tungstenit stream, a frame bigger than
+	// what WebSocketData::from_u8 accepts is refused before being buffered
+	let ws_config = WebSocketConfig {
+		max_message_size: Some(MAX_FRAME_SIZE as usize),
+		max_frame_size: Some(MAX_FRAME_SIZE as usize),
+		..WebSocketConfig::default()
+	};
 	let ws_stream = tokio_tungstenite::WebSocketStream::from_raw_socket(
 		upgraded,
 		tokio_tungstenite::tungstenite::protocol::Role::Server,
-		None,
+		Some(ws_config),
 	).await;
 	// create multithread stream to keep it in the mutex
 	let (tx, rx) = unbounded();
 	let id = Id::new(rand::random(), rand::random());
-	peers.lock().unwrap().insert(addr, (id, tx));
+	Span::current().record("id", id.to_name().as_str());
+	info!("connected");
+	// the client need the ice servers before creating any RtcPeerConnection,
+	// the turn credentials come first so they are ready at that point
+	if let Some(turn) = &config.turn {
+		let creds = turn::credentials(turn, &id.to_name(), SystemTime::now());
+		send_to(&tx, &WebSocketData::TurnCredentials(Some(creds)), &state.metrics);
+	}
+	send_to(&tx, &WebSocketData::IceServers(config.ice_servers.clone()), &state.metrics);
+	send_to(&tx, &WebSocketData::Neighbourhood(config.neighbourhood), &state.metrics);
+	// only the peers hold the sender: once removed (admin, resumed session)
+	// the connection ends after its close frame. A drain started during the
+	// upgrade did not tell this one to go away, checked under the lock the
+	// drain takes after setting the flag
+	let going_away = {
+		let mut peers = peers.lock().unwrap();
+		if state.shutting_down.load(Ordering::Relaxed) {
+			Some(tx)
+		} else {
+			peers.insert(addr, Peer::new(id, tx));
+			None
+		}
+	};
+	drop(slot);
+	if let Some(tx) = going_away {
+		state.metrics.upgrade_failure("shutting_down");
+		info!("server shutting down, closing");
+		send_to(&tx, &WebSocketData::GoingAway(config.timeouts.reconnect), &state.metrics);
+		close(&tx, CloseCode::Away, "Server shutting down", &state.metrics);
+		drop(tx);
+		let _ = timeout(CLOSE_TIMEOUT, rx.map(Ok).forward(ws_stream)).await;
+		return;
+	}
 	let (ws_sender, ws_receiver) = ws_stream.split();
 	// create new client
-	
+
+	// a client silent for too long is considered gone, our pings get a pong
+	// from the idle ones
+	let idle = config.timeouts.idle();
+	let every = idle / 3;
+	let pings = tokio::time::interval_at(tokio::time::Instant::now() + every, every).map(|_| Message::Ping(vec!()));
+	let ws_receiver = tokio::stream::StreamExt::timeout(ws_receiver, idle).map(|msg| match msg {
+		Ok(msg) => msg,
+		Err(_) => Err(Error::Protocol(Cow::Borrowed("Idle timeout")))
+	});
+
+	// a refused frame close the connection with a close frame of this code
+	let refuse = |code: CloseCode, reason: &'static str| {
+		if let Some(peer) = peers.lock().unwrap().get(&addr) {
+			close(&peer.tx, code, reason, &state.metrics);
+		}
+		future::err(Error::Protocol(Cow::Borrowed(reason)))
+	};
+
 	// broadcast_incoming stop when the stream stop
 	let broadcast_incoming = ws_receiver.try_for_each(|msg| {
+		if let Some(peer) = peers.lock().unwrap().get_mut(&addr) {
+			peer.last_activity = SystemTime::now();
+		}
+		// tungstenite answer the pings by itself
+		if msg.is_ping() || msg.is_pong() {
+			return future::ok(());
+		}
+		if msg.is_close() {
+			return future::err(Error::ConnectionClosed);
+		}
 		let msg = match WebSocketData::from_u8(msg.into_data()) {
 			Ok(msg) => msg,
 			Err(e) => {
-				eprintln!("Socket: error while parsing incomming message: {}", e);
-				return future::err(Error::Protocol(std::borrow::Cow::Borrowed("Invalid protocol")));
+				state.metrics.dropped_frame();
+				warn!("error while parsing incomming message: {}", e);
+				return refuse(CloseCode::Protocol, "Invalid protocol");
 			}
 		};
-		println!("Received a message from {}: {:?}", addr, msg);
+		if !state.limiter.allow(addr, &msg, Instant::now()) {
+			state.metrics.rate_limited(&msg);
+			warn!(message = metrics::variant(&msg), "rate limit exceeded, closing");
+			return refuse(CloseCode::Policy, "Rate limit exceeded");
+		}
+		debug!(message = metrics::variant(&msg), "received");
 		
 		// process the msg
-		let rsp = process(addr, msg, &peers);
+		let rsp = process(&state, addr, msg);
 		
 		if let Some(rsp) = rsp {
 			let rsp = match rsp.into_u8() {
 				Ok(rsp) => Message::binary(rsp),
 				Err(e) => {
-					eprintln!("Error while creating data from msg: {}", e);
-					return future::err(Error::Protocol(std::borrow::Cow::Borrowed("Internal Error")));
+					error!("Error while creating data from msg: {}", e);
+					return future::err(Error::Protocol(Cow::Borrowed("Internal Error")));
 				}
 			};
 			// TODO: remove those warning
 			match peers.lock().unwrap().get(&addr) {
-				Some((_id, sender)) => log_err(sender.unbounded_send(rsp)),
+				Some(peer) => log_err(peer.tx.unbounded_send(rsp)),
 				None => {
-					eprintln!("Cannot a reply to a phantom");
-					return future::err(Error::Protocol(std::borrow::Cow::Borrowed("Internal Error")));
+					error!("Cannot a reply to a phantom");
+					return future::err(Error::Protocol(Cow::Borrowed("Internal Error")));
 				}
 			};
 		}
 		future::ok(())
 		
 		// We want to broadcast the message to everyone except ourselves.
-		// future::err(Error::Protocol(std::borrow::Cow::Borrowed("lol")))
+		// future::err(Error::Protocol(Cow::Borrowed("lol")))
 	});
-	// forwarding everything comming from the unbound stream to the real stream
-	let receive_from_others = rx.map(Ok).forward(ws_sender);
+	// forwarding everything comming from the unbound stream to the real stream,
+	// the pings stop with it so dropping the sender end the connection
+	let outgoing = futures::stream::select(rx.map(Some).chain(futures::stream::once(future::ready(None))), pings.map(Some))
+		.take_while(|msg| future::ready(msg.is_some()))
+		.filter_map(future::ready);
+	let receive_from_others = outgoing.map(Ok).forward(ws_sender);
 	pin_mut!(broadcast_incoming, receive_from_others);
-	future::select(broadcast_incoming, receive_from_others).await;
+	// the close frame queued by a refused message still goes out
+	if let future::Either::Left((_, sending)) = future::select(broadcast_incoming, receive_from_others).await {
+		let _ = timeout(CLOSE_TIMEOUT, sending).await;
+	}
 
-	println!("{} disconnected", &addr);
-	peers.lock().unwrap().remove(&addr);
+	info!("disconnected");
+	let mut peers = peers.lock().unwrap();
+	peers.remove(&addr);
+	for peer in peers.values_mut() {
+		peer.offered.remove(&addr);
+		peer.brokered.remove(&addr);
+	}
+	state.relay.remove(&addr);
+	state.limiter.remove(addr);
 }
 
-pub async fn handler(peers: PeerMap, addr: SocketAddr, req: Request<Body>) -> Result<Response<Body>> {
-	// Websocket creation
-	let key = match req.headers().typed_get::<headers::SecWebsocketKey>() {
-		Some(key) => key,
-		None => return crate::send_static(req).await
+pub async fn handler(state: Arc<State>, addr: SocketAddr, req: Request<Body>) -> Result<Response<Body>> {
+	let config = &state.config;
+	let handshake = match handshake::validate(&req) {
+		Ok(handshake) => handshake,
+		Err(rejected) => {
+			state.metrics.upgrade_failure(rejected.as_str());
+			debug!(%addr, reason = rejected.as_str(), "invalid websocket handshake");
+			return rejected.response();
+		}
+	};
+	// our version first, the token protocol is only needed if the client did not offer it
+	let protocol = match admission::check(config, &req, SystemTime::now()) {
+		Ok(token) => handshake.protocol.or_else(|| token.map(str::to_string)),
+		Err(refused) => {
+			state.metrics.upgrade_failure(refused.as_str());
+			warn!(%addr, reason = refused.as_str(), "refusing peer: not admitted");
+			return Ok(Response::builder()
+				.status(StatusCode::FORBIDDEN)
+				.body(Body::from("Not allowed to join"))?);
+		}
+	};
+	if state.shutting_down.load(Ordering::Relaxed) {
+		state.metrics.upgrade_failure("shutting_down");
+		return Ok(Response::builder()
+			.status(StatusCode::SERVICE_UNAVAILABLE)
+			.body(Body::from("Server shutting down"))?);
+	}
+	// the peers and the upgrades in progress, under the same lock
+	let slot = {
+		let peers = state.peers.lock().unwrap();
+		let mut upgrading = state.upgrading.lock().unwrap();
+		if peers.len() + upgrading.values().sum::<usize>() >= config.max_peers {
+			state.metrics.upgrade_failure("full");
+			warn!(%addr, max_peers = config.max_peers, "refusing peer: max_peers reached");
+			return Ok(Response::builder()
+				.status(StatusCode::SERVICE_UNAVAILABLE)
+				.body(Body::from("Too many peers"))?);
+		}
+		let from_ip = upgrading.get(&addr.ip()).copied().unwrap_or(0);
+		if peers.keys().filter(|paddr| paddr.ip() == addr.ip()).count() + from_ip >= config.max_peers_per_ip {
+			state.metrics.upgrade_failure("ip_limit");
+			warn!(%addr, max_peers_per_ip = config.max_peers_per_ip, "refusing peer: too many connections from this ip");
+			return Ok(Response::builder()
+				.status(StatusCode::TOO_MANY_REQUESTS)
+				.body(Body::from("Too many connections from this address"))?);
+		}
+		*upgrading.entry(addr.ip()).or_insert(0) += 1;
+		Slot { state: state.clone(), ip: addr.ip() }
 	};
-    // spawn task that will be trigerd after the HTML response
-    println!("Upgrade starting...");
+	// spawn task that will be trigerd after the HTML response
+	debug!(%addr, "upgrade starting");
+	let span = info_span!("peer", %addr, id = field::Empty);
 	tokio::task::spawn(async move {
 		// transform the body into a future
-		match req.into_body().on_upgrade().await {
-			Ok(upgraded) => {
-				eprintln!("updrage receive");
-				upgrade(peers, addr, upgraded).await;
+		match timeout(state.config.timeouts.handshake(), req.into_body().on_upgrade()).await {
+			Ok(Ok(upgraded)) => {
+				upgrade(state, addr, upgraded, slot).instrument(span).await;
 			}
-			Err(e) => eprintln!("upgrade error: {}", e),
+			Ok(Err(e)) => {
+				state.metrics.upgrade_failure("error");
+				warn!(%addr, "upgrade error: {}", e)
+			},
+			Err(_) => {
+				state.metrics.upgrade_failure("timeout");
+				warn!(%addr, "upgrade timed out")
+			},
 		}
 	});
-	// Manual handshake response with headers crate
 	let mut rsp = Response::builder()
 		.status(StatusCode::SWITCHING_PROTOCOLS)
 		.body(Body::empty())?;
 	rsp.headers_mut().typed_insert(headers::Upgrade::websocket());
 	rsp.headers_mut().typed_insert(headers::Connection::upgrade());
-	rsp.headers_mut().typed_insert(headers::SecWebsocketAccept::from(key));
+	rsp.headers_mut().typed_insert(headers::SecWebsocketAccept::from(handshake.key));
+	// the client drop the connection if none of its subprotocols is selected
+	if let Some(protocol) = protocol {
+		rsp.headers_mut().insert(hyper::header::SEC_WEBSOCKET_PROTOCOL, protocol.parse()?);
+	}
 	Ok(rsp)
 }
\ No newline at end of file
@@ -0,0 +1,335 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{ Duration, SystemTime };
+use futures::{ future, Sink, SinkExt, Stream, StreamExt };
+use tokio::time::{ delay_for, timeout };
+use tokio_tungstenite::connect_async;
+use tungstenite::{ Error, Message };
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::handshake::client::Request;
+use crossplatform::id::{ Id, Metric };
+use crossplatform::topology::Neighbourhood;
+use crossplatform::proto_ws::{ WebSocketData, IceCandidateStruct, subprotocol };
+use crossplatform::codec::MAX_FRAME_SIZE;
+use server::{ State, listen };
+use server::config::{ Config, RelayConfig, Timeouts };
+
+// The server in process on a free port, with clients speaking WebSocketData
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const SILENCE: Duration = Duration::from_millis(200); // nothing received for this long
+
+async fn server() -> (Arc<State>, SocketAddr) {
+	server_with(Config::default()).await
+}
+
+async fn server_with(mut config: Config) -> (Arc<State>, SocketAddr) {
+	config.session.secret = Some("integration".to_string());
+	let state = Arc::new(State::new(config));
+	let (addr, server) = listen(state.clone(), &"127.0.0.1:0".parse().unwrap(), future::pending()).unwrap();
+	tokio::spawn(server);
+	(state, addr)
+}
+
+async fn connect(addr: SocketAddr) -> impl Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin {
+	let request = Request::builder()
+		.uri(format!("ws://{}/", addr))
+		.header("Sec-WebSocket-Protocol", subprotocol())
+		.body(())
+		.unwrap();
+	let (ws, _) = connect_async(request).await.expect("cannot connect");
+	ws
+}
+
+async fn send<S: Sink<Message, Error = Error> + Unpin>(ws: &mut S, msg: WebSocketData) {
+	ws.send(Message::binary(msg.into_u8().unwrap())).await.unwrap();
+}
+
+// The next WebSocketData, None once closed
+async fn recv<S: Stream<Item = Result<Message, Error>> + Unpin>(ws: &mut S) -> Option<WebSocketData> {
+	loop {
+		match timeout(TIMEOUT, ws.next()).await.expect("nothing received") {
+			Some(Ok(Message::Binary(data))) => return Some(WebSocketData::from_u8(data).unwrap()),
+			Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+			Some(Ok(_)) => continue
+		}
+	}
+}
+
+async fn silent<S: Stream<Item = Result<Message, Error>> + Unpin>(ws: &mut S) {
+	if let Ok(Some(msg)) = timeout(SILENCE, ws.next()).await {
+		panic!("unexpected message: {:?}", msg.map(|msg| WebSocketData::from_u8(msg.into_data())));
+	}
+}
+
+// The code of the close frame, the data before it is skipped
+async fn closed<S: Stream<Item = Result<Message, Error>> + Unpin>(ws: &mut S) -> CloseCode {
+	loop {
+		match timeout(TIMEOUT, ws.next()).await.expect("nothing received") {
+			Some(Ok(Message::Close(Some(frame)))) => return frame.code,
+			Some(Ok(Message::Binary(_))) => continue,
+			msg => panic!("expected a close frame, got {:?}", msg)
+		}
+	}
+}
+
+// Connected with `id` (a token of the server) or a random one
+async fn join(state: &State, addr: SocketAddr, id: Option<Id>) -> (impl Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin, Id) {
+	let mut ws = connect(addr).await;
+	match recv(&mut ws).await {
+		Some(WebSocketData::IceServers(ice)) => assert_eq!(ice, state.config.ice_servers),
+		msg => panic!("expected the ice servers, got {:?}", msg)
+	}
+	match recv(&mut ws).await {
+		Some(WebSocketData::Neighbourhood(neighbourhood)) => assert_eq!(neighbourhood, state.config.neighbourhood),
+		msg => panic!("expected the neighbourhood, got {:?}", msg)
+	}
+	let token = id.map(|id| state.sessions.token(id, SystemTime::now()));
+	send(&mut ws, WebSocketData::Resume(token)).await;
+	match recv(&mut ws).await {
+		Some(WebSocketData::Session(session, _)) => {
+			if let Some(id) = id {
+				assert_eq!(session, id);
+			}
+			(ws, session)
+		},
+		msg => panic!("expected a session, got {:?}", msg)
+	}
+}
+
+// Until the server noticed the disconnections
+async fn peers(state: &State, expected: usize) {
+	let wait = async {
+		while state.peers() != expected {
+			delay_for(Duration::from_millis(10)).await;
+		}
+	};
+	timeout(TIMEOUT, wait).await.expect("the server kept the peers");
+}
+
+#[tokio::test]
+async fn ids() {
+	let (state, addr) = server().await;
+	let (mut a, a_id) = join(&state, addr, None).await;
+	let (mut b, b_id) = join(&state, addr, None).await;
+	assert_ne!(a_id, b_id);
+	send(&mut a, WebSocketData::Id(None)).await;
+	assert!(matches!(recv(&mut a).await, Some(WebSocketData::Id(Some(id))) if id == a_id));
+	// an id cannot be chosen without a token
+	send(&mut b, WebSocketData::Id(Some(a_id))).await;
+	silent(&mut b).await;
+	send(&mut b, WebSocketData::Id(None)).await;
+	assert!(matches!(recv(&mut b).await, Some(WebSocketData::Id(Some(id))) if id == b_id));
+
+	// resumed by a new connection, the old one is closed
+	let (_c, c_id) = join(&state, addr, Some(a_id)).await;
+	assert_eq!(c_id, a_id);
+	assert!(recv(&mut a).await.is_none());
+	peers(&state, 2).await;
+}
+
+#[tokio::test]
+async fn signaling() {
+	let relay = RelayConfig { enabled: true, ..RelayConfig::default() };
+	let (state, addr) = server_with(Config { relay, ..Config::default() }).await;
+	let (mut a, _) = join(&state, addr, Some(Id::new(0, 0))).await;
+	// alone, nobody to answer
+	send(&mut a, WebSocketData::OfferSDP("alone".to_string(), None)).await;
+	silent(&mut a).await;
+
+	let (mut b, _) = join(&state, addr, Some(Id::new(10, 0))).await;
+	let (mut c, _) = join(&state, addr, Some(Id::new(1000, 0))).await;
+
+	// to the closest peer, with our address
+	send(&mut a, WebSocketData::OfferSDP("offer".to_string(), None)).await;
+	let a_addr = match recv(&mut b).await {
+		Some(WebSocketData::OfferSDP(sdp, Some(a_addr))) if sdp == "offer" => a_addr,
+		msg => panic!("expected the offer, got {:?}", msg)
+	};
+	silent(&mut c).await;
+	silent(&mut a).await;
+	// no relay before the answer
+	send(&mut b, WebSocketData::Relay(vec!(1), a_addr)).await;
+	assert_eq!(recv(&mut b).await, Some(WebSocketData::RelayClosed(a_addr)));
+
+	send(&mut b, WebSocketData::AnswerSDP("answer".to_string(), a_addr)).await;
+	let b_addr = match recv(&mut a).await {
+		Some(WebSocketData::AnswerSDP(sdp, b_addr)) if sdp == "answer" => b_addr,
+		msg => panic!("expected the answer, got {:?}", msg)
+	};
+	assert_ne!(a_addr, b_addr);
+	send(&mut a, WebSocketData::Relay(vec!(2), b_addr)).await;
+	assert_eq!(recv(&mut b).await, Some(WebSocketData::Relay(vec!(2), a_addr)));
+
+	let candidate = IceCandidateStruct { candidate: "candidate:1".to_string(), sdp_mid: Some("0".to_string()), sdp_m_line_index: Some(0) };
+	send(&mut a, WebSocketData::IceCandidate(candidate, b_addr)).await;
+	match recv(&mut b).await {
+		Some(WebSocketData::IceCandidate(candidate, from)) => {
+			assert_eq!(from, a_addr);
+			assert_eq!(candidate.candidate, "candidate:1");
+		},
+		msg => panic!("expected the candidate, got {:?}", msg)
+	}
+	silent(&mut c).await;
+
+	// an offer to a chosen peer
+	send(&mut c, WebSocketData::OfferSDP("direct".to_string(), Some(a_addr))).await;
+	assert!(matches!(recv(&mut a).await, Some(WebSocketData::OfferSDP(sdp, Some(_))) if sdp == "direct"));
+	silent(&mut b).await;
+	// only between the peers the server brokered
+	send(&mut c, WebSocketData::Relay(vec!(3), b_addr)).await;
+	assert_eq!(recv(&mut c).await, Some(WebSocketData::RelayClosed(b_addr)));
+	silent(&mut b).await;
+}
+
+#[tokio::test]
+async fn torus() {
+	let neighbourhood = Neighbourhood { metric: Metric::Torus, ..Neighbourhood::default() };
+	let config = Config { neighbourhood, ..Config::default() };
+	let (state, addr) = server_with(config).await;
+	let (mut a, _) = join(&state, addr, Some(Id::new(i32::MAX, 0))).await;
+	let (mut b, _) = join(&state, addr, Some(Id::new(0, 0))).await;
+	let (mut c, _) = join(&state, addr, Some(Id::new(i32::MIN, 0))).await;
+	// next to each other around the edge
+	send(&mut a, WebSocketData::OfferSDP("offer".to_string(), None)).await;
+	assert!(matches!(recv(&mut c).await, Some(WebSocketData::OfferSDP(sdp, Some(_))) if sdp == "offer"));
+	silent(&mut b).await;
+}
+
+#[tokio::test]
+async fn broadcast() {
+	let (state, addr) = server().await;
+	let (mut a, _) = join(&state, addr, None).await;
+	let (mut b, _) = join(&state, addr, None).await;
+	let (mut c, _) = join(&state, addr, None).await;
+	send(&mut a, WebSocketData::Message("hello".to_string())).await;
+	for ws in [&mut b, &mut c].iter_mut() {
+		assert!(matches!(recv(ws).await, Some(WebSocketData::Message(msg)) if msg == "hello"));
+	}
+	// not to the sender
+	silent(&mut a).await;
+}
+
+#[tokio::test]
+async fn disconnect() {
+	let (state, addr) = server().await;
+	let (mut a, _) = join(&state, addr, Some(Id::new(0, 0))).await;
+	let (b, _) = join(&state, addr, Some(Id::new(10, 0))).await;
+	let (mut c, _) = join(&state, addr, Some(Id::new(1000, 0))).await;
+	assert_eq!(state.peers(), 3);
+
+	// b was the closest
+	drop(b);
+	peers(&state, 2).await;
+	send(&mut a, WebSocketData::OfferSDP("offer".to_string(), None)).await;
+	assert!(matches!(recv(&mut c).await, Some(WebSocketData::OfferSDP(sdp, Some(_))) if sdp == "offer"));
+
+	c.close().await.unwrap();
+	peers(&state, 1).await;
+	send(&mut a, WebSocketData::OfferSDP("offer".to_string(), None)).await;
+	silent(&mut a).await;
+	drop(a);
+	peers(&state, 0).await;
+}
+
+#[tokio::test]
+async fn oversized() {
+	let (state, addr) = server().await;
+	let (mut a, _) = join(&state, addr, None).await;
+	let (mut b, _) = join(&state, addr, None).await;
+	// not even relayed to b
+	let frame = vec!(0u8; MAX_FRAME_SIZE as usize + 1);
+	let _ = a.send(Message::binary(frame)).await;
+	assert!(recv(&mut a).await.is_none());
+	silent(&mut b).await;
+	peers(&state, 1).await;
+
+	// invalid, not decoded
+	b.send(Message::binary(vec!(0xff; 64))).await.unwrap();
+	assert_eq!(closed(&mut b).await, CloseCode::Protocol);
+	peers(&state, 0).await;
+}
+
+#[tokio::test]
+async fn rate_limited() {
+	let (state, addr) = server().await;
+	let (mut a, _) = join(&state, addr, None).await;
+	let (mut b, _) = join(&state, addr, None).await;
+	// the upgrade gave its slot back once in the peers
+	assert!(state.upgrading.lock().unwrap().is_empty());
+	let burst = state.config.rate_limits.message.burst;
+	for _ in 0..=burst {
+		send(&mut a, WebSocketData::Message("spam".to_string())).await;
+	}
+	assert_eq!(closed(&mut a).await, CloseCode::Policy);
+	peers(&state, 1).await;
+	// b shares the ip of a but not its buckets
+	send(&mut b, WebSocketData::Message("hello".to_string())).await;
+	for _ in 0..burst {
+		assert!(matches!(recv(&mut b).await, Some(WebSocketData::Message(_))));
+	}
+	silent(&mut b).await;
+	assert_eq!(state.peers(), 1);
+}
+
+#[tokio::test]
+async fn idle() {
+	let timeouts = Timeouts { idle: 1, ..Timeouts::default() };
+	let (state, addr) = server_with(Config { timeouts, ..Config::default() }).await;
+	let (mut a, _) = join(&state, addr, None).await;
+	let (_b, _) = join(&state, addr, None).await;
+	// reading answers the pings of the server, a silent client stays
+	assert!(timeout(Duration::from_secs(3), recv(&mut a)).await.is_err());
+	// not b, it never reads
+	assert_eq!(state.peers(), 1);
+}
+
+#[tokio::test]
+async fn kicked() {
+	let admin_token = "0123456789abcdef";
+	let (state, addr) = server_with(Config { admin_token: Some(admin_token.to_string()), ..Config::default() }).await;
+	let (mut a, id) = join(&state, addr, None).await;
+	let (mut b, _) = join(&state, addr, None).await;
+	let request = hyper::Request::delete(format!("http://{}/admin/peers/{}", addr, id.to_name()))
+		.header("Authorization", format!("Bearer {}", admin_token))
+		.body(hyper::Body::empty())
+		.unwrap();
+	let rsp = hyper::Client::new().request(request).await.unwrap();
+	assert_eq!(rsp.status(), hyper::StatusCode::OK);
+	assert_eq!(state.peers(), 1);
+	// a does not answer the close frame, the server does not read it anymore
+	delay_for(SILENCE).await;
+	let _ = a.send(Message::binary(WebSocketData::Message("still here".to_string()).into_u8().unwrap())).await;
+	silent(&mut b).await;
+	assert_eq!(closed(&mut a).await, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn upgraded_while_draining() {
+	let (state, addr) = server().await;
+	// the drain starts once the upgrade is past its first check
+	let (locked, ready) = std::sync::mpsc::channel();
+	let draining = state.clone();
+	let drain = std::thread::spawn(move || {
+		let _peers = draining.peers.lock().unwrap();
+		locked.send(()).unwrap();
+		std::thread::sleep(Duration::from_millis(500));
+		draining.shutting_down.store(true, Ordering::Relaxed);
+	});
+	ready.recv().unwrap();
+	let mut a = connect(addr).await;
+	drain.join().unwrap();
+	loop {
+		match recv(&mut a).await {
+			Some(WebSocketData::GoingAway(window)) => {
+				assert_eq!(window, state.config.timeouts.reconnect);
+				break;
+			},
+			Some(_) => continue,
+			None => panic!("closed without GoingAway")
+		}
+	}
+	assert_eq!(closed(&mut a).await, CloseCode::Away);
+	assert_eq!(state.peers(), 0);
+}
@@ -0,0 +1,99 @@
+use structopt::StructOpt;
+use crossplatform::id::Metric;
+use crossplatform::topology::{ Neighbourhood, Slots };
+
+mod node;
+mod server;
+mod sim;
+
+use sim::{ Config, Simulation };
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "simulator", about = "Simulate a p2p chat overlay in memory")]
+struct Opt {
+	/// Number of nodes
+	#[structopt(short, long, default_value = "100")]
+	nodes: usize,
+
+	/// Seed of the simulation, the same seed gives the same report
+	#[structopt(short, long, default_value = "0")]
+	seed: u64,
+
+	/// The ids coordinates are drawn in -spread..spread
+	#[structopt(long, default_value = "16777216")]
+	spread: i32,
+
+	/// Neighbours kept by the nodes: sectors (top, left, right), nearest or buckets (Kademlia)
+	#[structopt(long, default_value = "sectors")]
+	slots: Slots,
+
+	/// Distance between the ids for sectors and nearest: manhattan or torus (wraps around)
+	#[structopt(long, default_value = "manhattan")]
+	metric: Metric,
+
+	/// Neighbours kept by nearest, and in each bucket
+	#[structopt(long, default_value = "4")]
+	neighbours: usize,
+
+	/// Simulated time in ms, the messages are sent in the second half
+	#[structopt(short, long, default_value = "120000")]
+	duration: u64,
+
+	/// Minimum latency of a link in ms
+	#[structopt(long, default_value = "10")]
+	min_latency: u64,
+
+	/// Maximum latency of a link in ms
+	#[structopt(long, default_value = "80")]
+	max_latency: u64,
+
+	/// Probability to lose a frame
+	#[structopt(long, default_value = "0")]
+	loss: f64,
+
+	/// Probability for a node to leave every second, replaced by a new one
+	#[structopt(long, default_value = "0")]
+	churn: f64,
+
+	/// Interval between two neighbour gossips of a node in ms
+	#[structopt(long, default_value = "5000")]
+	gossip_interval: u64,
+
+	/// Number of broadcast messages
+	#[structopt(long, default_value = "20")]
+	broadcasts: usize,
+
+	/// Number of private messages
+	#[structopt(long, default_value = "100")]
+	privates: usize,
+
+	/// Frames forwarded more than this are dropped
+	#[structopt(long, default_value = "32")]
+	max_hops: u32
+}
+
+fn main() {
+	let opt = Opt::from_args();
+	for probability in [opt.loss, opt.churn].iter() {
+		if !(0.0..=1.0).contains(probability) {
+			eprintln!("a probability is between 0 and 1, got {}", probability);
+			std::process::exit(1);
+		}
+	}
+	let config = Config {
+		nodes: opt.nodes,
+		seed: opt.seed,
+		spread: opt.spread,
+		neighbourhood: Neighbourhood { slots: opt.slots, metric: opt.metric, k: opt.neighbours },
+		duration: opt.duration,
+		latency: (opt.min_latency, opt.max_latency),
+		loss: opt.loss,
+		churn: opt.churn,
+		gossip_interval: opt.gossip_interval,
+		broadcasts: opt.broadcasts,
+		privates: opt.privates,
+		max_hops: opt.max_hops,
+		..Config::default()
+	};
+	println!("{}", Simulation::new(config).run());
+}
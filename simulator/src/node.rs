@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use crossplatform::id::Id;
+use crossplatform::overlay::{ Overlay, PeerLink, Transport, Ui };
+use crossplatform::topology::Neighbourhood;
+
+// The overlay nodes of the simulation, everything they send or ask for
+// is queued here and handled by the simulator
+
+#[derive(Debug)]
+pub struct Frame {
+	pub from: Id,
+	pub to: Id,
+	pub data: Vec<u8>
+}
+
+// In memory data channel
+#[derive(Debug)]
+pub struct Link {
+	from: Id,
+	to: Id,
+	outbox: Rc<RefCell<Vec<Frame>>>,
+	closed: Rc<RefCell<Vec<(Id, Id)>>>
+}
+
+impl PeerLink for Link {
+	fn send(&self, data: &[u8]) {
+		self.outbox.borrow_mut().push(Frame { from: self.from, to: self.to, data: data.to_vec() });
+	}
+
+	fn close(&self) {
+		self.closed.borrow_mut().push((self.from, self.to));
+	}
+}
+
+// Connections asked by the overlay, signaled through it by the simulator
+#[derive(Debug)]
+pub struct Connector {
+	me: Id,
+	requests: Rc<RefCell<Vec<(Id, Id)>>>,
+	pub pending: RefCell<HashSet<Id>>
+}
+
+impl Transport for Connector {
+	type Link = Link;
+
+	fn connect(&self, id: Id) {
+		if self.pending.borrow_mut().insert(id) {
+			self.requests.borrow_mut().push((self.me, id));
+		}
+	}
+
+	fn is_pending(&self, id: Id) -> bool {
+		self.pending.borrow().contains(&id)
+	}
+}
+
+// Records the chat and the neighbours instead of showing them
+#[derive(Debug)]
+pub struct Screen {
+	me: Id,
+	received: Rc<RefCell<Vec<(Id, String)>>>,
+	pub neighbours: RefCell<Vec<Id>>
+}
+
+impl Ui for Screen {
+	fn chat_msg(&self, _from: &str, msg: &str) {
+		self.received.borrow_mut().push((self.me, msg.to_string()));
+	}
+
+	fn chat_private(&self, _from: &str, msg: &str) {
+		self.received.borrow_mut().push((self.me, msg.to_string()));
+	}
+
+	fn chat_info(&self, _msg: &str) { }
+
+	fn neighbours(&self, neighbours: &[(String, Id)], _cache: &[Id]) {
+		*self.neighbours.borrow_mut() = neighbours.iter().map(|(_, id)| *id).collect();
+	}
+}
+
+pub type Node = Overlay<Connector, Screen>;
+
+// Queues shared by every node
+#[derive(Debug, Default)]
+pub struct Shared {
+	pub outbox: Rc<RefCell<Vec<Frame>>>,
+	pub requests: Rc<RefCell<Vec<(Id, Id)>>>, // (from, to)
+	pub closed: Rc<RefCell<Vec<(Id, Id)>>>, // (by, peer)
+	pub received: Rc<RefCell<Vec<(Id, String)>>> // (by, message)
+}
+
+impl Shared {
+	pub fn node(&self, id: Id, neighbourhood: Neighbourhood) -> Node {
+		let connector = Connector { me: id, requests: self.requests.clone(), pending: RefCell::new(HashSet::new()) };
+		let screen = Screen { me: id, received: self.received.clone(), neighbours: RefCell::new(vec!()) };
+		Overlay::new(id, neighbourhood, connector, screen)
+	}
+
+	pub fn link(&self, from: Id, to: Id) -> Link {
+		Link { from, to, outbox: self.outbox.clone(), closed: self.closed.clone() }
+	}
+}
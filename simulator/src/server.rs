@@ -0,0 +1,51 @@
+use crossplatform::id::Id;
+use crossplatform::topology::{ Topology, Neighbourhood };
+
+// The signaling server: a new peer is connected to the closest connected one,
+// the same choice as `closest_peer` in the server process.rs
+
+#[derive(Debug, Default)]
+pub struct Server {
+	topology: Neighbourhood,
+	peers: Vec<Id> // in connection order, like the websockets
+}
+
+impl Server {
+	pub fn new(topology: Neighbourhood) -> Self {
+		Server { topology, peers: vec!() }
+	}
+
+	// The peer the newcomer gets connected to, if not alone
+	pub fn join(&mut self, id: Id) -> Option<Id> {
+		let closest = self.topology.closest(id, self.peers.iter().map(|peer| (*peer, *peer)));
+		self.peers.push(id);
+		closest
+	}
+
+	pub fn leave(&mut self, id: Id) {
+		self.peers.retain(|peer| *peer != id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crossplatform::id::{ Id, Metric };
+	use crossplatform::topology::Neighbourhood;
+	use super::Server;
+
+	#[test]
+	fn closest() {
+		let mut server = Server::default();
+		let (a, b, c) = (Id::new(0, 0), Id::new(10, 10), Id::new(9, 9));
+		assert_eq!(server.join(a), None);
+		assert_eq!(server.join(b), Some(a));
+		assert_eq!(server.join(c), Some(b));
+		server.leave(b);
+		assert_eq!(server.join(Id::new(8, 8)), Some(c));
+
+		let mut server = Server::new(Neighbourhood { metric: Metric::Torus, ..Neighbourhood::default() });
+		server.join(Id::new(i32::MIN, 0));
+		server.join(Id::new(0, 0));
+		assert_eq!(server.join(Id::new(i32::MAX, 0)), Some(Id::new(i32::MIN, 0)));
+	}
+}
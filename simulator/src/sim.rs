@@ -0,0 +1,528 @@
+use std::cmp::Ordering;
+use std::collections::{ BTreeMap, BinaryHeap };
+use std::fmt;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+use crossplatform::id::Id;
+use crossplatform::proto_rtc::{ RTCData, RTCContent };
+use crossplatform::topology::{ Topology, Neighbourhood };
+use crate::node::{ Frame, Node, Shared };
+use crate::server::Server;
+
+// Discrete event simulation: a seeded rng and a virtual clock (ms),
+// the same config and seed always give the same report
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub nodes: usize,
+	pub seed: u64,
+	pub spread: i32, // coordinates of the ids in -spread..spread
+	pub neighbourhood: Neighbourhood, // of the server and every node
+	pub duration: u64, // ms, the traffic is sent in the second half
+	pub join_interval: u64, // ms between two of the first joins
+	pub latency: (u64, u64), // ms, min and max of a link
+	pub loss: f64, // probability to lose a frame
+	pub churn: f64, // probability for a node to leave every second, replaced by a new one
+	pub gossip_interval: u64, // ms
+	pub connect_timeout: u64, // ms before giving up a connection signaled through the overlay
+	pub broadcasts: usize,
+	pub privates: usize,
+	pub max_hops: u32 // frames forwarded more are dropped
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			nodes: 100,
+			seed: 0,
+			spread: 1 << 24,
+			neighbourhood: Neighbourhood::default(),
+			duration: 120_000,
+			join_interval: 50,
+			latency: (10, 80),
+			loss: 0.0,
+			churn: 0.0,
+			gossip_interval: 5_000,
+			connect_timeout: 5_000,
+			broadcasts: 20,
+			privates: 100,
+			max_hops: 32
+		}
+	}
+}
+
+#[derive(Debug)]
+enum Action {
+	Join,
+	Churn,
+	Deliver(Frame, u32), // with its hop count
+	Gossip(Id),
+	Expire(Id, Id), // connection from, to
+	Broadcast,
+	Private
+}
+
+#[derive(Debug)]
+struct Event {
+	time: u64,
+	seq: u64, // scheduling order for the events at the same time
+	action: Action
+}
+
+// Earliest first in the BinaryHeap
+impl Ord for Event {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(other.time, other.seq).cmp(&(self.time, self.seq))
+	}
+}
+
+impl PartialOrd for Event {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl PartialEq for Event {
+	fn eq(&self, other: &Self) -> bool {
+		(self.time, self.seq) == (other.time, other.seq)
+	}
+}
+
+impl Eq for Event { }
+
+#[derive(Debug)]
+struct Message {
+	private: bool,
+	expected: usize, // nodes that should get it
+	hops: BTreeMap<u64, u32>, // first reception by node
+	receptions: usize
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Delivery {
+	pub sent: usize,
+	pub expected: usize,
+	pub delivered: usize,
+	pub hops: usize, // sum over the first receptions
+	pub duplicates: usize
+}
+
+impl Delivery {
+	pub fn ratio(&self) -> f64 {
+		if self.expected == 0 { 1.0 } else { self.delivered as f64 / self.expected as f64 }
+	}
+
+	pub fn mean_hops(&self) -> f64 {
+		if self.delivered == 0 { 0.0 } else { self.hops as f64 / self.delivered as f64 }
+	}
+}
+
+impl fmt::Display for Delivery {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} sent, delivery {:.1}% ({}/{}), {:.2} hops, {} duplicates",
+			self.sent, self.ratio() * 100.0, self.delivered, self.expected, self.mean_hops(), self.duplicates)
+	}
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+	pub nodes: usize, // at the end
+	pub links: usize,
+	pub joins: usize,
+	pub leaves: usize,
+	pub connects: usize, // asked through the overlay
+	pub connected: usize,
+	pub frames: usize,
+	pub lost: usize,
+	pub dead: usize, // frames for a node that left
+	pub hop_limited: usize,
+	pub no_route: usize,
+	pub nearest: f64, // share of the slots holding the true nearest nodes
+	pub broadcast: Delivery,
+	pub private: Delivery
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "nodes: {} ({} joins, {} leaves), links: {}", self.nodes, self.joins, self.leaves, self.links)?;
+		writeln!(f, "overlay connections: {}/{}, nearest neighbours: {:.1}%", self.connected, self.connects, self.nearest * 100.0)?;
+		writeln!(f, "frames: {}, lost: {}, to dead nodes: {}, hop limited: {}, no route: {}",
+			self.frames, self.lost, self.dead, self.hop_limited, self.no_route)?;
+		writeln!(f, "broadcast: {}", self.broadcast)?;
+		write!(f, "private: {}", self.private)
+	}
+}
+
+pub struct Simulation {
+	config: Config,
+	rng: StdRng,
+	now: u64,
+	seq: u64,
+	queue: BinaryHeap<Event>,
+	nodes: BTreeMap<u64, Node>, // ordered for the determinism
+	server: Server,
+	shared: Shared,
+	messages: Vec<Message>,
+	report: Report
+}
+
+impl Simulation {
+	pub fn new(config: Config) -> Self {
+		let neighbourhood = config.neighbourhood;
+		Simulation {
+			rng: StdRng::seed_from_u64(config.seed),
+			config,
+			now: 0,
+			seq: 0,
+			queue: BinaryHeap::new(),
+			nodes: BTreeMap::new(),
+			server: Server::new(neighbourhood),
+			shared: Shared::default(),
+			messages: vec!(),
+			report: Report::default()
+		}
+	}
+
+	pub fn run(mut self) -> Report {
+		for i in 0..self.config.nodes {
+			self.schedule(i as u64 * self.config.join_interval, Action::Join);
+		}
+		if self.config.churn > 0.0 {
+			self.schedule(1000, Action::Churn);
+		}
+		let half = self.config.duration / 2;
+		for _ in 0..self.config.broadcasts {
+			let delay = self.rng.gen_range(half, self.config.duration.max(half + 1));
+			self.schedule(delay, Action::Broadcast);
+		}
+		for _ in 0..self.config.privates {
+			let delay = self.rng.gen_range(half, self.config.duration.max(half + 1));
+			self.schedule(delay, Action::Private);
+		}
+		// the periodic events stop at `duration`, then the queue drains
+		while let Some(event) = self.queue.pop() {
+			self.now = event.time;
+			self.step(event.action);
+		}
+		self.finish()
+	}
+
+	fn schedule(&mut self, delay: u64, action: Action) {
+		self.seq += 1;
+		self.queue.push(Event { time: self.now + delay, seq: self.seq, action });
+	}
+
+	fn running(&self) -> bool {
+		self.now < self.config.duration
+	}
+
+	fn step(&mut self, action: Action) {
+		match action {
+			Action::Join => self.join(),
+			Action::Churn => self.churn(),
+			Action::Deliver(frame, hops) => self.deliver(frame, hops),
+			Action::Gossip(id) => {
+				if self.running() {
+					if let Some(node) = self.nodes.get_mut(&id.0) {
+						// only fails to serialize
+						node.gossip().expect("gossip");
+						let interval = self.config.gossip_interval;
+						self.schedule(interval, Action::Gossip(id));
+					}
+				}
+				self.flush(0);
+			},
+			Action::Expire(from, to) => {
+				if let Some(node) = self.nodes.get(&from.0) {
+					node.transport().pending.borrow_mut().remove(&to);
+				}
+			},
+			Action::Broadcast => self.send(false),
+			Action::Private => self.send(true)
+		}
+	}
+
+	fn link(&mut self, a: Id, b: Id) {
+		let link = self.shared.link(a, b);
+		if let Some(node) = self.nodes.get_mut(&a.0) {
+			node.insert(link, b);
+		}
+		let link = self.shared.link(b, a);
+		if let Some(node) = self.nodes.get_mut(&b.0) {
+			node.insert(link, a);
+		}
+	}
+
+	fn join(&mut self) {
+		let spread = self.config.spread.max(1);
+		let id = Id::new(self.rng.gen_range(-spread, spread), self.rng.gen_range(-spread, spread));
+		if self.nodes.contains_key(&id.0) {
+			return;
+		}
+		self.report.joins += 1;
+		self.nodes.insert(id.0, self.shared.node(id, self.config.neighbourhood));
+		if let Some(peer) = self.server.join(id) {
+			self.link(id, peer);
+		}
+		let phase = self.rng.gen_range(0, self.config.gossip_interval.max(1));
+		self.schedule(phase, Action::Gossip(id));
+	}
+
+	fn leave(&mut self, id: Id) {
+		self.report.leaves += 1;
+		self.nodes.remove(&id.0);
+		self.server.leave(id);
+		for node in self.nodes.values_mut() {
+			// only the ones linked with it know it
+			let _ = node.remove(id);
+			node.transport().pending.borrow_mut().remove(&id);
+		}
+	}
+
+	fn churn(&mut self) {
+		if !self.running() {
+			return;
+		}
+		let churn = self.config.churn;
+		let leaving = self.nodes.values().map(|node| node.id).collect::<Vec<_>>().into_iter()
+			.filter(|_| self.rng.gen_bool(churn))
+			.collect::<Vec<_>>();
+		for id in leaving {
+			self.leave(id);
+			self.join();
+		}
+		self.schedule(1000, Action::Churn);
+	}
+
+	fn random_node(&mut self, except: Option<Id>) -> Option<Id> {
+		let ids = self.nodes.values().map(|node| node.id).filter(|id| Some(*id) != except).collect::<Vec<_>>();
+		if ids.is_empty() {
+			return None;
+		}
+		Some(ids[self.rng.gen_range(0, ids.len())])
+	}
+
+	fn send(&mut self, private: bool) {
+		let from = match self.random_node(None) {
+			Some(from) => from,
+			None => return
+		};
+		let to = if private {
+			match self.random_node(Some(from)) {
+				Some(to) => Some(to),
+				None => return
+			}
+		} else {
+			None
+		};
+		let number = self.messages.len();
+		self.messages.push(Message {
+			private,
+			expected: if private { 1 } else { self.nodes.len() - 1 },
+			hops: BTreeMap::new(),
+			receptions: 0
+		});
+		let content = RTCContent::Message(number.to_string());
+		let node = &self.nodes[&from.0];
+		let sent = match to {
+			Some(to) => node.send_to(content, to),
+			None => node.broadcast(content)
+		};
+		if sent.is_err() {
+			self.report.no_route += 1;
+		}
+		self.flush(0);
+	}
+
+	// Schedule what the nodes sent while handling a frame of `hops` hops
+	fn flush(&mut self, hops: u32) {
+		loop {
+			// the other side of the links dropped by the overlays
+			let closed = self.shared.closed.borrow_mut().drain(..).collect::<Vec<_>>();
+			for (by, peer) in closed {
+				if let Some(node) = self.nodes.get_mut(&peer.0) {
+					let _ = node.remove(by);
+				}
+			}
+			let requests = self.shared.requests.borrow_mut().drain(..).collect::<Vec<_>>();
+			for (from, to) in requests {
+				self.report.connects += 1;
+				let routed = match self.nodes.get(&from.0) {
+					Some(node) => node.send_to(RTCContent::Offer(String::new()), to).is_ok(),
+					None => false
+				};
+				if !routed {
+					self.report.no_route += 1;
+				}
+				self.schedule(self.config.connect_timeout, Action::Expire(from, to));
+			}
+			let frames = self.shared.outbox.borrow_mut().drain(..).collect::<Vec<_>>();
+			if frames.is_empty() {
+				break;
+			}
+			for frame in frames {
+				self.report.frames += 1;
+				if self.rng.gen_bool(self.config.loss) {
+					self.report.lost += 1;
+					continue;
+				}
+				let (min, max) = self.config.latency;
+				let latency = self.rng.gen_range(min, max.max(min) + 1);
+				self.schedule(latency, Action::Deliver(frame, hops + 1));
+			}
+		}
+	}
+
+	fn deliver(&mut self, frame: Frame, hops: u32) {
+		if hops > self.config.max_hops {
+			self.report.hop_limited += 1;
+			return;
+		}
+		let data = match (self.nodes.get(&frame.to.0), RTCData::from_u8(frame.data)) {
+			(Some(_), Ok(data)) => data,
+			_ => {
+				self.report.dead += 1;
+				return;
+			}
+		};
+		let me = frame.to;
+		match data.content {
+			// the signaling, the data channel opens with the answer
+			RTCContent::Offer(_) if data.to == Some(me) => {
+				let node = &self.nodes[&me.0];
+				if !node.contains(data.from) && node.send_to(RTCContent::Answer(String::new()), data.from).is_err() {
+					self.report.no_route += 1;
+				}
+			},
+			RTCContent::Answer(_) if data.to == Some(me) => {
+				let node = &self.nodes[&me.0];
+				let asked = node.transport().pending.borrow_mut().remove(&data.from);
+				if asked && self.nodes.contains_key(&data.from.0) && !node.contains(data.from) {
+					if let Some(peer) = self.nodes.get(&data.from.0) {
+						peer.transport().pending.borrow_mut().remove(&me);
+					}
+					self.report.connected += 1;
+					self.link(me, data.from);
+				}
+			},
+			_ => {
+				// every copy reaching a recipient, the overlay drops the duplicates
+				if let RTCContent::Message(ref msg) = data.content {
+					if data.to.is_none() || data.to == Some(me) {
+						if let Some(message) = msg.parse::<usize>().ok().and_then(|number| self.messages.get_mut(number)) {
+							message.receptions += 1;
+						}
+					}
+				}
+				if self.nodes[&me.0].process(&data, frame.from).is_err() {
+					self.report.no_route += 1;
+				}
+			}
+		}
+		let received = self.shared.received.borrow_mut().drain(..).collect::<Vec<_>>();
+		for (by, msg) in received {
+			if let Some(message) = msg.parse::<usize>().ok().and_then(|number| self.messages.get_mut(number)) {
+				message.hops.entry(by.0).or_insert(hops);
+			}
+		}
+		self.flush(hops);
+	}
+
+	// Share of the places in the slots of the nodes held by one of the nearest
+	// nodes of that slot
+	fn nearest(&self) -> f64 {
+		let (mut filled, mut nearest) = (0, 0);
+		let topology = self.config.neighbourhood;
+		for node in self.nodes.values() {
+			let mut slots: BTreeMap<usize, Vec<u64>> = BTreeMap::new();
+			for other in self.nodes.values().filter(|other| other.id != node.id) {
+				slots.entry(topology.slot(node.id, other.id)).or_default().push(topology.distance(node.id, other.id));
+			}
+			let neighbours = node.ui().neighbours.borrow();
+			for (slot, mut distances) in slots {
+				distances.sort_unstable();
+				distances.truncate(topology.capacity());
+				let furthest = *distances.last().unwrap();
+				let kept = neighbours.iter()
+					.filter(|id| topology.slot(node.id, **id) == slot && topology.distance(node.id, **id) <= furthest)
+					.count();
+				filled += distances.len();
+				nearest += kept.min(distances.len());
+			}
+		}
+		if filled == 0 { 1.0 } else { nearest as f64 / filled as f64 }
+	}
+
+	fn finish(mut self) -> Report {
+		self.report.nodes = self.nodes.len();
+		self.report.links = self.nodes.values().map(|node| node.len()).sum::<usize>() / 2;
+		self.report.nearest = self.nearest();
+		for message in self.messages.iter() {
+			let delivery = if message.private { &mut self.report.private } else { &mut self.report.broadcast };
+			delivery.sent += 1;
+			delivery.expected += message.expected;
+			delivery.delivered += message.hops.len();
+			delivery.hops += message.hops.values().map(|hops| *hops as usize).sum::<usize>();
+			delivery.duplicates += message.receptions - message.hops.len();
+		}
+		self.report
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crossplatform::id::Metric;
+	use crossplatform::topology::{ Neighbourhood, Slots };
+	use super::{ Config, Simulation };
+
+	fn small() -> Config {
+		Config { nodes: 30, duration: 30_000, broadcasts: 5, privates: 20, ..Config::default() }
+	}
+
+	#[test]
+	fn deterministic() {
+		let config = Config { loss: 0.05, churn: 0.01, ..small() };
+		let report = Simulation::new(config.clone()).run();
+		assert_eq!(report, Simulation::new(config.clone()).run());
+		assert_ne!(report, Simulation::new(Config { seed: 1, ..config }).run());
+	}
+
+	#[test]
+	fn stable_network() {
+		let report = Simulation::new(small()).run();
+		assert_eq!(report.nodes, 30);
+		assert_eq!((report.lost, report.dead), (0, 0));
+		// every join is linked to the network, nothing is lost
+		assert_eq!(report.broadcast.sent, 5);
+		assert_eq!(report.broadcast.delivered, report.broadcast.expected);
+		assert!(report.links >= 29);
+		assert!(report.connected > 0);
+		assert!(report.private.delivered > 0);
+	}
+
+	#[test]
+	fn whole_range() {
+		for metric in [Metric::Manhattan, Metric::Torus].iter() {
+			let neighbourhood = Neighbourhood { metric: *metric, ..Neighbourhood::default() };
+			let report = Simulation::new(Config { spread: i32::MAX, neighbourhood, ..small() }).run();
+			assert_eq!(report.broadcast.delivered, report.broadcast.expected);
+			assert!(report.links >= 29);
+		}
+	}
+
+	// The same network with every kind of slots
+	#[test]
+	fn topologies() {
+		let reports = [Slots::Sectors, Slots::Nearest, Slots::Buckets].iter().map(|slots| {
+			let neighbourhood = Neighbourhood { slots: *slots, k: 3, ..Neighbourhood::default() };
+			let report = Simulation::new(Config { neighbourhood, ..small() }).run();
+			assert_eq!(report.broadcast.delivered, report.broadcast.expected, "{:?}", slots);
+			assert!(report.links >= 29 && report.private.delivered > 0, "{:?}", slots);
+			report
+		}).collect::<Vec<_>>();
+		// more links, shorter routes
+		let (sectors, buckets) = (&reports[0], &reports[2]);
+		assert!(buckets.links > sectors.links);
+		assert!(buckets.private.delivered >= sectors.private.delivered);
+	}
+}
@@ -0,0 +1,198 @@
+use crossterm::event::{ KeyCode, KeyEvent, KeyModifiers };
+use crossplatform::id::Id;
+use client::client::Output;
+
+// State of the screen, fed by the outputs of the client and the keys
+
+const MAX_ENTRIES: usize = 1000; // older messages are dropped
+const PAGE: usize = 10; // lines scrolled by page up / down
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+	Message(String, String), // from, text
+	Private(String, String),
+	Info(String),
+	Error(String)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+	Send(String), // a line for the client
+	Quit
+}
+
+#[derive(Default)]
+pub struct App {
+	pub id: Option<Id>,
+	pub status: String, // of the server
+	pub entries: Vec<Entry>,
+	pub neighbours: Vec<(String, Id)>, // with the label of their slot
+	pub cache: Vec<Id>,
+	pub input: String,
+	pub cursor: usize, // in chars
+	pub scroll: usize, // lines above the bottom of the chat
+	history: Vec<String>,
+	recall: Option<usize> // index in history
+}
+
+impl App {
+	pub fn new() -> Self {
+		App { status: "connecting...".to_string(), ..Default::default() }
+	}
+
+	fn push(&mut self, entry: Entry) {
+		self.entries.push(entry);
+		if self.entries.len() > MAX_ENTRIES {
+			self.entries.remove(0);
+		}
+	}
+
+	pub fn output(&mut self, output: Output) {
+		match output {
+			Output::Message(from, text) => self.push(Entry::Message(from, text)),
+			Output::Private(from, text) => self.push(Entry::Private(from, text)),
+			Output::Info(info) => self.push(Entry::Info(info)),
+			Output::Error(e) => self.push(Entry::Error(e)),
+			Output::Neighbours(neighbours, cache) => {
+				self.neighbours = neighbours;
+				self.cache = cache;
+			},
+			Output::Status(status) => self.status = status,
+			Output::Id(id) => self.id = Some(id)
+		}
+	}
+
+	fn byte(&self, cursor: usize) -> usize {
+		self.input.char_indices().nth(cursor).map_or(self.input.len(), |(i, _)| i)
+	}
+
+	fn set_input(&mut self, input: String) {
+		self.cursor = input.chars().count();
+		self.input = input;
+	}
+
+	pub fn key(&mut self, key: KeyEvent) -> Option<Action> {
+		let len = self.input.chars().count();
+		match key.code {
+			KeyCode::Char('c') | KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(Action::Quit),
+			KeyCode::Esc => return Some(Action::Quit),
+			KeyCode::Char(c) => {
+				let at = self.byte(self.cursor);
+				self.input.insert(at, c);
+				self.cursor += 1;
+			},
+			KeyCode::Backspace if self.cursor > 0 => {
+				self.cursor -= 1;
+				let at = self.byte(self.cursor);
+				self.input.remove(at);
+			},
+			KeyCode::Delete if self.cursor < len => {
+				let at = self.byte(self.cursor);
+				self.input.remove(at);
+			},
+			KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+			KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+			KeyCode::Home => self.cursor = 0,
+			KeyCode::End => self.cursor = len,
+			KeyCode::PageUp => self.scroll += PAGE,
+			KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+			KeyCode::Up if !self.history.is_empty() => {
+				let recall = self.recall.map_or(self.history.len() - 1, |i| i.saturating_sub(1));
+				self.recall = Some(recall);
+				self.set_input(self.history[recall].clone());
+			},
+			KeyCode::Down => match self.recall {
+				Some(i) if i + 1 < self.history.len() => {
+					self.recall = Some(i + 1);
+					self.set_input(self.history[i + 1].clone());
+				},
+				_ => {
+					self.recall = None;
+					self.set_input(String::new());
+				}
+			},
+			KeyCode::Enter => {
+				let line = std::mem::take(&mut self.input);
+				self.cursor = 0;
+				self.recall = None;
+				self.scroll = 0;
+				if line.trim().is_empty() {
+					return None;
+				}
+				if line.trim() == "/quit" {
+					return Some(Action::Quit);
+				}
+				self.history.push(line.clone());
+				return Some(Action::Send(line));
+			},
+			_ => ()
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crossterm::event::{ KeyCode, KeyEvent, KeyModifiers };
+	use crossplatform::id::Id;
+	use client::client::Output;
+	use super::{ App, Action, Entry };
+
+	fn key(app: &mut App, code: KeyCode) -> Option<Action> {
+		app.key(KeyEvent::new(code, KeyModifiers::NONE))
+	}
+
+	fn typing(app: &mut App, text: &str) {
+		text.chars().for_each(|c| { key(app, KeyCode::Char(c)); });
+	}
+
+	#[test]
+	fn editing() {
+		let mut app = App::new();
+		typing(&mut app, "hllo");
+		key(&mut app, KeyCode::Home);
+		key(&mut app, KeyCode::Right);
+		typing(&mut app, "é");
+		key(&mut app, KeyCode::End);
+		key(&mut app, KeyCode::Backspace);
+		typing(&mut app, "o!");
+		assert_eq!(app.input, "héllo!");
+		assert_eq!(key(&mut app, KeyCode::Enter), Some(Action::Send("héllo!".to_string())));
+		assert_eq!(app.input, "");
+		assert_eq!(key(&mut app, KeyCode::Enter), None);
+
+		// history
+		typing(&mut app, "/peers");
+		key(&mut app, KeyCode::Enter);
+		key(&mut app, KeyCode::Up);
+		key(&mut app, KeyCode::Up);
+		assert_eq!(app.input, "héllo!");
+		key(&mut app, KeyCode::Down);
+		assert_eq!(app.input, "/peers");
+		key(&mut app, KeyCode::Down);
+		assert_eq!(app.input, "");
+
+		typing(&mut app, "/quit");
+		assert_eq!(key(&mut app, KeyCode::Enter), Some(Action::Quit));
+		assert_eq!(app.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Some(Action::Quit));
+	}
+
+	#[test]
+	fn outputs() {
+		let mut app = App::new();
+		let (a, b) = (Id::new(1, 2), Id::new(-3, 4));
+		app.output(Output::Id(a));
+		app.output(Output::Status("connected, 1 peers".to_string()));
+		app.output(Output::Neighbours(vec!(("Top".to_string(), b)), vec!(a, b)));
+		app.output(Output::Message("x".to_string(), "hi".to_string()));
+		assert_eq!(app.id, Some(a));
+		assert_eq!(app.status, "connected, 1 peers");
+		assert_eq!((app.neighbours.clone(), app.cache.len()), (vec!(("Top".to_string(), b)), 2));
+		assert_eq!(app.entries, vec!(Entry::Message("x".to_string(), "hi".to_string())));
+		for i in 0..2000 {
+			app.output(Output::Info(i.to_string()));
+		}
+		assert_eq!(app.entries.len(), super::MAX_ENTRIES);
+		assert_eq!(app.entries.last(), Some(&Entry::Info("1999".to_string())));
+	}
+}
@@ -0,0 +1,98 @@
+use std::io::{ self, Stdout };
+use structopt::StructOpt;
+use tokio::sync::mpsc::unbounded_channel;
+use crossterm::event::{ self, Event as TermEvent, KeyEventKind };
+use crossterm::execute;
+use crossterm::terminal::{ disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen };
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use client::client::{ Client, Config, Event };
+use client::signaling;
+
+mod app;
+mod ui;
+
+use app::{ Action, App };
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tui", about = "Full screen p2p chat in a terminal")]
+struct Opt {
+	/// Websocket of the server
+	#[structopt(short, long, default_value = "ws://localhost:8088")]
+	server: String,
+
+	/// Admission token, when the server requires one
+	#[structopt(short, long)]
+	token: Option<String>
+}
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+fn setup() -> io::Result<Term> {
+	enable_raw_mode()?;
+	execute!(io::stdout(), EnterAlternateScreen)?;
+	Terminal::new(CrosstermBackend::new(io::stdout()))
+}
+
+fn restore() {
+	let _ = disable_raw_mode();
+	let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+#[tokio::main]
+async fn main() {
+	let opt = Opt::from_args();
+	let config = Config { url: signaling::url(&opt.server, opt.token.as_deref()) };
+	let (client, events, receiver, mut outputs) = Client::new(config);
+
+	// give the terminal back before the panic message
+	let hook = std::panic::take_hook();
+	std::panic::set_hook(Box::new(move |info| {
+		restore();
+		hook(info);
+	}));
+	let mut terminal = match setup() {
+		Ok(terminal) => terminal,
+		Err(e) => {
+			restore();
+			eprintln!("Cannot setup the terminal: {}", e);
+			std::process::exit(1);
+		}
+	};
+
+	// crossterm reads are blocking
+	let (keys, mut keys_rx) = unbounded_channel();
+	std::thread::spawn(move || {
+		while let Ok(event) = event::read() {
+			if keys.send(event).is_err() {
+				break;
+			}
+		}
+	});
+	tokio::spawn(client.run(receiver));
+
+	let mut app = App::new();
+	loop {
+		if let Err(e) = terminal.draw(|frame| ui::draw(frame, &mut app)) {
+			restore();
+			eprintln!("Cannot draw: {}", e);
+			std::process::exit(1);
+		}
+		tokio::select! {
+			output = outputs.recv() => match output {
+				Some(output) => app.output(output),
+				None => break // the client stopped
+			},
+			Some(event) = keys_rx.recv() => match event {
+				TermEvent::Key(key) if key.kind != KeyEventKind::Release => match app.key(key) {
+					Some(Action::Send(line)) => { let _ = events.send(Event::Input(line)); },
+					Some(Action::Quit) => break,
+					None => ()
+				},
+				_ => () // a resize is redrawn by the loop
+			}
+		}
+	}
+	let _ = events.send(Event::Input("/quit".to_string()));
+	restore();
+}
@@ -0,0 +1,119 @@
+use std::convert::TryFrom;
+use ratatui::Frame;
+use ratatui::layout::{ Constraint, Direction, Layout };
+use ratatui::style::{ Color, Modifier, Style };
+use ratatui::text::{ Line, Span };
+use ratatui::widgets::{ Block, Borders, Paragraph, Wrap };
+use crossplatform::id::Id;
+use crate::app::{ App, Entry };
+
+// Same layout as static/index.html: name and server status on top, the chat
+// and its input on the left, the peers on the right
+
+fn name(id: Option<Id>) -> String {
+	id.map_or("None".to_string(), |id| id.to_name())
+}
+
+fn entry(entry: &Entry) -> Line<'_> {
+	let bold = Style::default().add_modifier(Modifier::BOLD);
+	let italic = Style::default().add_modifier(Modifier::ITALIC);
+	match entry {
+		Entry::Message(from, text) => Line::from(vec!(Span::styled(format!("{}: ", from), bold), Span::raw(text.as_str()))),
+		Entry::Private(from, text) => Line::from(vec!(
+			Span::styled(format!("{}: ", from), bold.add_modifier(Modifier::ITALIC)),
+			Span::styled(text.as_str(), italic)
+		)),
+		Entry::Info(info) => Line::from(Span::styled(info.as_str(), italic.fg(Color::DarkGray))),
+		Entry::Error(e) => Line::from(Span::styled(e.as_str(), bold.fg(Color::Red)))
+	}
+}
+
+fn peer(label: &str, id: Option<Id>) -> Line<'static> {
+	Line::from(vec!(Span::styled(format!("{}: ", label), Style::default().add_modifier(Modifier::BOLD)), Span::raw(name(id))))
+}
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+	let rows = Layout::default()
+		.direction(Direction::Vertical)
+		.constraints([Constraint::Length(1), Constraint::Min(3)])
+		.split(frame.size());
+	let columns = Layout::default()
+		.direction(Direction::Horizontal)
+		.constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+		.split(rows[1]);
+	let chat = Layout::default()
+		.direction(Direction::Vertical)
+		.constraints([Constraint::Min(1), Constraint::Length(3)])
+		.split(columns[0]);
+
+	let header = Line::from(vec!(
+		Span::styled("P2P Chat", Style::default().add_modifier(Modifier::BOLD)),
+		Span::raw(format!("   Name: {}   Server: {}", name(app.id), app.status))
+	));
+	frame.render_widget(Paragraph::new(header), rows[0]);
+
+	// the last lines, or above them when scrolled
+	let messages = Paragraph::new(app.entries.iter().map(entry).collect::<Vec<_>>())
+		.wrap(Wrap { trim: false });
+	let (width, height) = (chat[0].width.saturating_sub(2), chat[0].height.saturating_sub(2) as usize);
+	let max = messages.line_count(width).saturating_sub(height);
+	app.scroll = app.scroll.min(max);
+	let offset = u16::try_from(max - app.scroll).unwrap_or(u16::MAX);
+	frame.render_widget(messages.scroll((offset, 0)).block(Block::default().borders(Borders::ALL).title("Chat")), chat[0]);
+
+	// keep the cursor visible in a long input
+	let visible = chat[1].width.saturating_sub(3) as usize;
+	let skip = app.cursor.saturating_sub(visible);
+	let input = app.input.chars().skip(skip).collect::<String>();
+	frame.render_widget(Paragraph::new(input).block(Block::default().borders(Borders::ALL).title("Message")), chat[1]);
+	frame.set_cursor(chat[1].x + 1 + (app.cursor - skip) as u16, chat[1].y + 1);
+
+	let cache = Line::from(vec!(Span::styled("Cache: ", Style::default().add_modifier(Modifier::BOLD)), Span::raw(app.cache.len().to_string())));
+	let mut peers = app.neighbours.iter().map(|(label, id)| peer(label, Some(*id))).collect::<Vec<_>>();
+	if peers.is_empty() {
+		peers.push(peer("Neighbours", None));
+	}
+	peers.push(cache);
+	peers.extend(app.cache.iter().map(|id| Line::from(format!("  {}", id.to_name()))));
+	frame.render_widget(Paragraph::new(peers).block(Block::default().borders(Borders::ALL).title("Peers")), columns[1]);
+}
+
+#[cfg(test)]
+mod tests {
+	use ratatui::Terminal;
+	use ratatui::backend::TestBackend;
+	use crossplatform::id::Id;
+	use client::client::Output;
+	use crate::app::App;
+	use super::draw;
+
+	fn screen(app: &mut App) -> String {
+		let mut terminal = Terminal::new(TestBackend::new(80, 12)).unwrap();
+		terminal.draw(|frame| draw(frame, app)).unwrap();
+		let buffer = terminal.backend().buffer();
+		buffer.content.chunks(80).map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>()).collect::<Vec<_>>().join("\n")
+	}
+
+	#[test]
+	fn layout() {
+		let mut app = App::new();
+		let (me, top) = (Id::new(1, 2), Id::new(5, 9));
+		app.output(Output::Id(me));
+		app.output(Output::Neighbours(vec!(("Top".to_string(), top)), vec!()));
+		for i in 0..20 {
+			app.output(Output::Message("bob".to_string(), format!("message {}", i)));
+		}
+		let text = screen(&mut app);
+		assert!(text.contains(&format!("Name: {}", me.to_name())));
+		assert!(text.contains(&format!("Top: {}", top.to_name())));
+		assert!(text.contains("bob: message 19"), "{}", text);
+		assert!(!text.contains("bob: message 0 "));
+
+		// scrolled to the first message, no further
+		app.scroll = 1000;
+		let text = screen(&mut app);
+		assert!(text.contains("bob: message 0 "), "{}", text);
+		assert!(!text.contains("message 19"));
+		assert_eq!(app.scroll, 20 - 6); // 6 lines in the chat
+	}
+}